@@ -0,0 +1,119 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! D-Bus service, see `serve`
+
+use std::path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use zbus::object_server::SignalEmitter;
+
+use crate::{refresh_rate, scan_displays, Display, ScanOptions};
+
+/// Well-known name of the service on the session bus
+const NAME: &str = "io.github.rokbma.rres";
+
+/// Path of the service object
+const PATH: &str = "/io/github/rokbma/rres";
+
+/// A display, as index, card, connector name, width, height and refresh rate, `(ussuud)`
+type Entry = (u32, String, String, u32, u32, f64);
+
+/// The `io.github.rokbma.rres1` interface
+struct Service {
+    cards: Vec<path::PathBuf>,
+    scan: Arc<ScanOptions>,
+    timeout: Option<Duration>,
+}
+
+#[zbus::interface(name = "io.github.rokbma.rres1")]
+impl Service {
+    /// Read the displays
+    fn get_displays(&self) -> zbus::fdo::Result<Vec<Entry>> {
+        let displays = scan_displays(&self.cards, &self.scan, self.timeout)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))?;
+        Ok(entries(&displays))
+    }
+
+    /// Sent while watching, when the displays change
+    #[zbus(signal)]
+    async fn displays_changed(emitter: &SignalEmitter<'_>, displays: Vec<Entry>)
+        -> zbus::Result<()>;
+}
+
+fn entries(displays: &[Display]) -> Vec<Entry> {
+    displays
+        .iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let (x, y) = d.mode.size();
+            let refresh = refresh_rate(&d.mode);
+            (i as u32, d.card.clone(), d.connector.clone(), x.into(), y.into(), refresh)
+        })
+        .collect()
+}
+
+/// Serve the displays on the session bus, until killed
+///
+/// `GetDisplays` reads the displays on every call. With an `interval`, they are also read
+/// periodically, sending `DisplaysChanged` when they change. A failed read is logged and
+/// retried on the next tick.
+pub fn serve(
+    cards: Vec<path::PathBuf>,
+    scan: Arc<ScanOptions>,
+    timeout: Option<Duration>,
+    interval: Option<Duration>,
+) -> eyre::Result<()> {
+    let service = Service {
+        cards: cards.clone(),
+        scan: Arc::clone(&scan),
+        timeout,
+    };
+    let conn = zbus::blocking::connection::Builder::session()?
+        .name(NAME)?
+        .serve_at(PATH, service)?
+        .build()?;
+    log::info!("Serving {} on the session bus", NAME);
+    let interval = match interval {
+        Some(interval) => interval,
+        // The connection handles the calls on its own thread
+        None => loop {
+            thread::park();
+        },
+    };
+    let service = conn.object_server().interface::<_, Service>(PATH)?;
+    let mut last: Option<Vec<Entry>> = None;
+    loop {
+        let displays = match scan_displays(&cards, &scan, timeout) {
+            Ok(displays) => entries(&displays),
+            Err(e) => {
+                log::error!("{:#}", e);
+                thread::sleep(interval);
+                continue;
+            }
+        };
+        if last.as_ref().is_some_and(|last| *last != displays) {
+            // Generated signals are async, and this loop isn't
+            let signal = Service::displays_changed(service.signal_emitter(), displays.clone());
+            async_io::block_on(signal)?;
+        }
+        last = Some(displays);
+        thread::sleep(interval);
+    }
+}
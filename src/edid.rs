@@ -0,0 +1,636 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Minimal EDID parser
+//!
+//! Only the parts of the EDID rres reports on are decoded.
+
+use crate::mode::{self, Mode};
+
+/// Size of an EDID block
+const BLOCK_SIZE: usize = 128;
+
+/// Fixed header of the EDID base block
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+/// Tag of a CTA-861 extension block
+const CTA_TAG: u8 = 0x02;
+
+/// CTA-861 data block tag of audio data blocks
+const CTA_AUDIO: u8 = 1;
+
+/// CTA-861 data block tag of vendor specific data blocks
+const CTA_VENDOR: u8 = 3;
+
+/// IEEE OUI of AMD, in the byte order of vendor specific data blocks
+const AMD_OUI: [u8; 3] = [0x1a, 0x00, 0x00];
+
+/// CIE 1931 xy coordinates of the display primaries and white point
+pub struct Chromaticity {
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white: (f64, f64),
+}
+
+/// Manufacturer and product identification
+pub struct Identity {
+    /// Three letter PNP manufacturer ID, e.g. DEL
+    pub manufacturer: String,
+    /// Manufacturer's product code
+    pub product: u16,
+    /// Numeric serial number, often 0
+    pub serial: u32,
+    /// Serial number from the display descriptors
+    pub serial_string: Option<String>,
+}
+
+/// Video input definition
+pub enum VideoInput {
+    /// Digital input, the interface and bit depth are only defined since EDID 1.4
+    Digital {
+        interface: Option<&'static str>,
+        bit_depth: Option<u8>,
+    },
+    Analog,
+}
+
+/// Audio format from a CTA-861 short audio descriptor
+pub struct AudioFormat {
+    pub format: &'static str,
+    /// Maximum number of channels
+    pub channels: u8,
+}
+
+/// Where the EDID of a display comes from
+#[derive(Clone, Copy)]
+pub enum Source {
+    /// Read from the display by the kernel, with no override in place
+    Live,
+    /// Replaced by `--edid-override`, or the kernel's `edid_firmware` or debugfs override
+    Overridden,
+    Unknown,
+}
+
+/// Raw EDID data
+#[derive(Clone)]
+pub struct Edid(Vec<u8>);
+
+impl Edid {
+    /// Parse EDID data, checking that it looks like a valid EDID
+    pub fn parse(bytes: Vec<u8>) -> eyre::Result<Self> {
+        if bytes.len() < BLOCK_SIZE {
+            return Err(eyre::eyre!("EDID too short ({} bytes)", bytes.len()));
+        }
+        if bytes[..8] != HEADER {
+            return Err(eyre::eyre!("invalid EDID header"));
+        }
+        Ok(Edid(bytes))
+    }
+
+    /// Raw bytes, including every extension block
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Whether every block's checksum is valid
+    pub fn checksum_valid(&self) -> bool {
+        self.0
+            .chunks_exact(BLOCK_SIZE)
+            .all(|block| block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0)
+    }
+
+    /// The base EDID block
+    fn base(&self) -> &[u8] {
+        &self.0[..BLOCK_SIZE]
+    }
+
+    /// EDID version and revision, e.g. (1, 4)
+    pub fn version(&self) -> (u8, u8) {
+        (self.0[18], self.0[19])
+    }
+
+    /// Manufacturer, product and serial from the base block
+    pub fn identity(&self) -> Identity {
+        let b = self.base();
+        // Three 5 bit letters, 1 is A
+        let id = u16::from_be_bytes([b[8], b[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| char::from(b'A' - 1 + (id >> shift & 0x1f) as u8))
+            .collect();
+        Identity {
+            manufacturer,
+            product: u16::from_le_bytes([b[10], b[11]]),
+            serial: u32::from_le_bytes([b[12], b[13], b[14], b[15]]),
+            serial_string: self.descriptor_string(0xff),
+        }
+    }
+
+    /// Monitor name from the display descriptors, e.g. DELL U2720Q
+    pub fn monitor_name(&self) -> Option<String> {
+        self.descriptor_string(0xfc)
+    }
+
+    /// Text of the first display descriptor with `tag` in the base block
+    fn descriptor_string(&self, tag: u8) -> Option<String> {
+        self.base()[54..126]
+            .chunks_exact(18)
+            .find(|d| d[..3] == [0, 0, 0] && d[3] == tag)
+            .map(|d| {
+                // Text ends with a newline, then gets padded with spaces
+                let text = d[5..].split(|c| *c == b'\n').next().unwrap_or_default();
+                String::from_utf8_lossy(text).trim().to_string()
+            })
+    }
+
+    /// Physical size of the image, in millimeters
+    ///
+    /// The first detailed timing has it to the millimeter, the base block only in centimeters.
+    /// Projectors and some TVs leave both at 0.
+    pub fn physical_size(&self) -> Option<(u32, u32)> {
+        let b = self.base();
+        let dtd = &b[54..72];
+        if dtd[0] != 0 || dtd[1] != 0 {
+            let x = u32::from(dtd[12]) | u32::from(dtd[14] >> 4) << 8;
+            let y = u32::from(dtd[13]) | u32::from(dtd[14] & 0x0f) << 8;
+            if x != 0 && y != 0 {
+                return Some((x, y));
+            }
+        }
+        if b[21] != 0 && b[22] != 0 {
+            return Some((u32::from(b[21]) * 10, u32::from(b[22]) * 10));
+        }
+        None
+    }
+
+    /// Video input definition from the base block
+    pub fn video_input(&self) -> VideoInput {
+        let input = self.base()[20];
+        if input & 0x80 == 0 {
+            return VideoInput::Analog;
+        }
+        if self.version() < (1, 4) {
+            return VideoInput::Digital {
+                interface: None,
+                bit_depth: None,
+            };
+        }
+        let bit_depth = match input >> 4 & 0b111 {
+            0 | 7 => None,
+            n => Some(4 + 2 * n),
+        };
+        let interface = match input & 0x0f {
+            1 => Some("DVI"),
+            2 => Some("HDMI-a"),
+            3 => Some("HDMI-b"),
+            4 => Some("MDDI"),
+            5 => Some("DisplayPort"),
+            _ => None,
+        };
+        VideoInput::Digital {
+            interface,
+            bit_depth,
+        }
+    }
+
+    /// Color characteristics from the base block
+    pub fn chromaticity(&self) -> Chromaticity {
+        let b = self.base();
+        // 10 bit values: the 2 low bits are packed in bytes 25 and 26
+        let coord = |high: usize, low: u8, shift: u8| {
+            let raw = u16::from(b[high]) << 2 | u16::from(low >> shift & 0b11);
+            f64::from(raw) / 1024.0
+        };
+        Chromaticity {
+            red: (coord(27, b[25], 6), coord(28, b[25], 4)),
+            green: (coord(29, b[25], 2), coord(30, b[25], 0)),
+            blue: (coord(31, b[26], 6), coord(32, b[26], 4)),
+            white: (coord(33, b[26], 2), coord(34, b[26], 0)),
+        }
+    }
+
+    /// Extension blocks that are actually present in the data
+    fn extensions(&self) -> impl Iterator<Item = &[u8]> {
+        self.0[BLOCK_SIZE..].chunks_exact(BLOCK_SIZE)
+    }
+
+    /// CTA-861 extension blocks
+    fn cta_blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.extensions().filter(|b| b[0] == CTA_TAG)
+    }
+
+    /// Data blocks of the CTA-861 extensions, as their tag and payload
+    fn cta_data_blocks(&self) -> Vec<(u8, &[u8])> {
+        let mut blocks = vec![];
+        for block in self.cta_blocks() {
+            // Data blocks go from byte 4 up to the detailed timings
+            let end = usize::from(block[2]).min(127);
+            let mut i = 4;
+            while i < end {
+                let tag = block[i] >> 5;
+                let len = usize::from(block[i] & 0x1f);
+                if i + 1 + len > end {
+                    break;
+                }
+                blocks.push((tag, &block[i + 1..i + 1 + len]));
+                i += 1 + len;
+            }
+        }
+        blocks
+    }
+
+    /// Audio formats from the CTA-861 short audio descriptors
+    pub fn audio_formats(&self) -> Vec<AudioFormat> {
+        self.cta_data_blocks()
+            .into_iter()
+            .filter(|(tag, _)| *tag == CTA_AUDIO)
+            .flat_map(|(_, payload)| payload.chunks_exact(3))
+            .map(|sad| AudioFormat {
+                format: match sad[0] >> 3 & 0x0f {
+                    1 => "PCM",
+                    2 => "AC-3",
+                    3 => "MPEG-1",
+                    4 => "MP3",
+                    5 => "MPEG-2",
+                    6 => "AAC",
+                    7 => "DTS",
+                    8 => "ATRAC",
+                    9 => "DSD",
+                    10 => "E-AC-3",
+                    11 => "DTS-HD",
+                    12 => "TrueHD",
+                    13 => "DST",
+                    14 => "WMA Pro",
+                    _ => "unknown",
+                },
+                channels: (sad[0] & 0b111) + 1,
+            })
+            .collect()
+    }
+
+    /// Variable refresh rate range, in Hz
+    ///
+    /// This comes from the AMD FreeSync vendor specific data block when it flags FreeSync as
+    /// supported. Without one, the display range limits descriptor is only a variable range when
+    /// the EDID is 1.4 or later and flags continuous frequencies, like the kernel checks: EDID
+    /// 1.3 requires that descriptor from fixed rate displays too.
+    pub fn vrr_range(&self) -> Option<(u16, u16)> {
+        // OUI, version, feature flags, then the minimum and maximum rates
+        let amd = self
+            .cta_data_blocks()
+            .into_iter()
+            .find(|(tag, payload)| *tag == CTA_VENDOR && payload.starts_with(&AMD_OUI))
+            .filter(|(_, payload)| payload.len() >= 7 && payload[4] & 1 != 0);
+        if let Some((_, payload)) = amd {
+            return Some((payload[5].into(), payload[6].into()));
+        }
+        if self.version() < (1, 4) || self.base()[24] & 1 == 0 {
+            return None;
+        }
+        self.base()[54..126]
+            .chunks_exact(18)
+            .find(|d| d[..3] == [0, 0, 0] && d[3] == 0xfd)
+            .map(|d| {
+                // Since EDID 1.4, flags add 255 to the rates
+                let offset = |bit: u8| if d[4] & bit != 0 { 255 } else { 0 };
+                (u16::from(d[5]) + offset(0b01), u16::from(d[6]) + offset(0b10))
+            })
+    }
+
+    /// Resolutions from the standard timings of the base block
+    pub fn standard_timings(&self) -> Vec<(u32, u32)> {
+        let mut timings = vec![];
+        for pair in self.base()[38..54].chunks_exact(2) {
+            // 0x01 0x01 marks an unused slot
+            if pair == [0x01, 0x01] || pair[0] == 0 {
+                continue;
+            }
+            let x = (u32::from(pair[0]) + 31) * 8;
+            let y = match pair[1] >> 6 {
+                // 16:10, or 1:1 before EDID 1.3
+                0 if self.version() < (1, 3) => x,
+                0 => x * 10 / 16,
+                1 => x * 3 / 4,
+                2 => x * 4 / 5,
+                _ => x * 9 / 16,
+            };
+            timings.push((x, y));
+        }
+        timings
+    }
+
+    /// Detailed timing descriptors of the base block, then of the CTA-861 extensions
+    fn timing_descriptors(&self) -> Vec<&[u8]> {
+        let mut descriptors: Vec<&[u8]> = self.base()[54..126].chunks_exact(18).collect();
+        for block in self.cta_blocks() {
+            let offset = usize::from(block[2]);
+            if (4..BLOCK_SIZE).contains(&offset) {
+                descriptors.extend(block[offset..127].chunks_exact(18));
+            }
+        }
+        // A zero pixel clock marks a display descriptor, not a timing
+        descriptors.retain(|d| d[0] != 0 || d[1] != 0);
+        descriptors
+    }
+
+    /// Modes of the detailed timing descriptors, including CTA-861 ones
+    ///
+    /// Like the kernel, the first one is preferred when the EDID is 1.4 or later, or flags it as
+    /// the preferred timing. Interlaced modes get frame heights, as DRM modes do.
+    pub fn modes(&self) -> Vec<Mode> {
+        let preferred = self.version() >= (1, 4) || self.base()[24] & 0b10 != 0;
+        let mut modes: Vec<Mode> = self
+            .timing_descriptors()
+            .into_iter()
+            .map(|d| {
+                let low_high = |low: u8, high: u8| u16::from(low) | u16::from(high) << 8;
+                let hdisplay = low_high(d[2], d[4] >> 4);
+                let hblank = low_high(d[3], d[4] & 0x0f);
+                let vdisplay = low_high(d[5], d[7] >> 4);
+                let vblank = low_high(d[6], d[7] & 0x0f);
+                let hsync_offset = low_high(d[8], d[11] >> 6);
+                let hsync_width = low_high(d[9], d[11] >> 4 & 0b11);
+                let vsync_offset = u16::from(d[10] >> 4) | u16::from(d[11] >> 2 & 0b11) << 4;
+                let vsync_width = u16::from(d[10] & 0x0f) | u16::from(d[11] & 0b11) << 4;
+                let hsync = if d[17] & 0b10 != 0 { mode::FLAG_PHSYNC } else { mode::FLAG_NHSYNC };
+                let vsync = if d[17] & 0b100 != 0 { mode::FLAG_PVSYNC } else { mode::FLAG_NVSYNC };
+                let mut mode = Mode {
+                    clock: u32::from(u16::from_le_bytes([d[0], d[1]])) * 10,
+                    hdisplay,
+                    hsync_start: hdisplay + hsync_offset,
+                    hsync_end: hdisplay + hsync_offset + hsync_width,
+                    htotal: hdisplay + hblank,
+                    vdisplay,
+                    vsync_start: vdisplay + vsync_offset,
+                    vsync_end: vdisplay + vsync_offset + vsync_width,
+                    vtotal: vdisplay + vblank,
+                    vrefresh: 0,
+                    flags: hsync | vsync,
+                    preferred: false,
+                };
+                let mut fields = 1;
+                if d[17] & 0x80 != 0 {
+                    // Vertical timings are per field
+                    mode.flags |= mode::FLAG_INTERLACE;
+                    mode.vdisplay *= 2;
+                    mode.vsync_start *= 2;
+                    mode.vsync_end *= 2;
+                    mode.vtotal = (mode.vtotal * 2) | 1;
+                    fields = 2;
+                }
+                // Rounded like the kernel's drm_mode_vrefresh()
+                let pixels = u64::from(mode.htotal) * u64::from(mode.vtotal);
+                let rate = u64::from(mode.clock) * 1000 * fields;
+                mode.vrefresh = (rate + pixels / 2).checked_div(pixels).unwrap_or(0) as u32;
+                mode
+            })
+            .collect();
+        if let Some(first) = modes.first_mut() {
+            first.preferred = preferred;
+        }
+        modes
+    }
+
+    /// Resolutions from the detailed timing descriptors, including CTA-861 ones
+    pub fn detailed_timings(&self) -> Vec<(u32, u32)> {
+        self.timing_descriptors()
+            .into_iter()
+            .map(|d| {
+                let x = u32::from(d[2]) | u32::from(d[4] >> 4) << 8;
+                let y = u32::from(d[5]) | u32::from(d[7] >> 4) << 8;
+                (x, y)
+            })
+            .collect()
+    }
+
+    /// Largest resolution advertised by the EDID, by area
+    pub fn max_resolution(&self) -> Option<(u32, u32)> {
+        self.standard_timings()
+            .into_iter()
+            .chain(self.detailed_timings())
+            .max_by_key(|(x, y)| x * y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EDID 1.3 of a 24" 1920x1200 monitor, laid out like a Dell U2412M's
+    const MONITOR: [u8; 128] = [
+        0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x10, 0xac, 0x7a, 0xa0,
+        0x53, 0x4c, 0x4e, 0x30, 0x1a, 0x16, 0x01, 0x03, 0x80, 0x34, 0x20, 0x78,
+        0xea, 0xee, 0x95, 0xa3, 0x54, 0x4c, 0x99, 0x26, 0x0f, 0x50, 0x54, 0xa5,
+        0x4b, 0x00, 0xd1, 0x00, 0xb3, 0x00, 0x81, 0x80, 0x81, 0x40, 0x71, 0x4f,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x28, 0x3c, 0x80, 0xa0, 0x70, 0xb0,
+        0x23, 0x40, 0x30, 0x20, 0x36, 0x00, 0x06, 0x44, 0x21, 0x00, 0x00, 0x1a,
+        0x00, 0x00, 0x00, 0xff, 0x00, 0x50, 0x4e, 0x39, 0x56, 0x58, 0x31, 0x32,
+        0x41, 0x30, 0x41, 0x42, 0x4c, 0x0a, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x44,
+        0x45, 0x4c, 0x4c, 0x20, 0x55, 0x32, 0x34, 0x31, 0x32, 0x4d, 0x0a, 0x20,
+        0x00, 0x00, 0x00, 0xfd, 0x00, 0x38, 0x4c, 0x1e, 0x51, 0x11, 0x00, 0x0a,
+        0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x95,
+    ];
+    /// EDID 1.4 of a 4K TV, with a CTA-861 extension holding video, audio, speaker, HDMI and
+    /// FreeSync data blocks and a 720p detailed timing
+    const TV: [u8; 256] = [
+        0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x4c, 0x2d, 0x78, 0x0f,
+        0x00, 0x00, 0x00, 0x00, 0x2e, 0x1d, 0x01, 0x04, 0xa2, 0x79, 0x44, 0x78,
+        0x0a, 0x23, 0xad, 0xa4, 0x54, 0x4d, 0x99, 0x26, 0x0e, 0x47, 0x4a, 0xbd,
+        0xef, 0x80, 0x71, 0x4f, 0x81, 0xc0, 0x81, 0x00, 0x81, 0x80, 0x95, 0x00,
+        0xa9, 0xc0, 0xb3, 0x00, 0x01, 0x01, 0x08, 0xe8, 0x00, 0x30, 0xf2, 0x70,
+        0x5a, 0x80, 0xb0, 0x58, 0x8a, 0x00, 0xb9, 0xa8, 0x42, 0x00, 0x00, 0x1e,
+        0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40, 0x58, 0x2c, 0x45, 0x00,
+        0xb9, 0xa8, 0x42, 0x00, 0x00, 0x1e, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x18,
+        0x4b, 0x0f, 0x87, 0x3c, 0x00, 0x0a, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+        0x00, 0x00, 0x00, 0xfc, 0x00, 0x53, 0x41, 0x4d, 0x53, 0x55, 0x4e, 0x47,
+        0x0a, 0x20, 0x20, 0x20, 0x20, 0x20, 0x01, 0x1c, 0x02, 0x03, 0x24, 0xf0,
+        0x45, 0x90, 0x61, 0x04, 0x1f, 0x03, 0x26, 0x09, 0x07, 0x07, 0x15, 0x07,
+        0x50, 0x83, 0x01, 0x00, 0x00, 0x65, 0x03, 0x0c, 0x00, 0x10, 0x00, 0x68,
+        0x1a, 0x00, 0x00, 0x01, 0x01, 0x30, 0x78, 0x00, 0x01, 0x1d, 0x00, 0x72,
+        0x51, 0xd0, 0x1e, 0x20, 0x6e, 0x28, 0x55, 0x00, 0xb9, 0xa8, 0x42, 0x00,
+        0x00, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x13,
+    ];
+
+
+    fn round3((x, y): (f64, f64)) -> (f64, f64) {
+        ((x * 1000.0).round() / 1000.0, (y * 1000.0).round() / 1000.0)
+    }
+
+    #[test]
+    fn parse_checks_header_and_size() {
+        assert!(Edid::parse(MONITOR.to_vec()).is_ok());
+        assert!(Edid::parse(MONITOR[..127].to_vec()).is_err());
+        let mut bytes = MONITOR.to_vec();
+        bytes[0] = 0xff;
+        assert!(Edid::parse(bytes).is_err());
+    }
+
+    #[test]
+    fn checksums() {
+        assert!(Edid::parse(MONITOR.to_vec()).unwrap().checksum_valid());
+        assert!(Edid::parse(TV.to_vec()).unwrap().checksum_valid());
+        let mut bytes = TV.to_vec();
+        bytes[200] ^= 1;
+        assert!(!Edid::parse(bytes).unwrap().checksum_valid());
+    }
+
+    #[test]
+    fn monitor_identity() {
+        let edid = Edid::parse(MONITOR.to_vec()).unwrap();
+        assert_eq!(edid.version(), (1, 3));
+        let id = edid.identity();
+        assert_eq!(id.manufacturer, "DEL");
+        assert_eq!(id.product, 0xa07a);
+        assert_eq!(id.serial, 0x304e4c53);
+        assert_eq!(id.serial_string.as_deref(), Some("PN9VX12A0ABL"));
+        assert_eq!(edid.monitor_name().as_deref(), Some("DELL U2412M"));
+        assert_eq!(Edid::parse(TV.to_vec()).unwrap().identity().manufacturer, "SAM");
+    }
+
+    #[test]
+    fn physical_size() {
+        let monitor = Edid::parse(MONITOR.to_vec()).unwrap();
+        assert_eq!(monitor.physical_size(), Some((518, 324)));
+        // Without the detailed timing size, the base block one in centimeters
+        let mut bytes = MONITOR.to_vec();
+        bytes[66..69].fill(0);
+        assert_eq!(Edid::parse(bytes).unwrap().physical_size(), Some((520, 320)));
+        let tv = Edid::parse(TV.to_vec()).unwrap();
+        assert_eq!(tv.physical_size(), Some((1209, 680)));
+    }
+
+    #[test]
+    fn video_input() {
+        let monitor = Edid::parse(MONITOR.to_vec()).unwrap();
+        assert!(matches!(
+            monitor.video_input(),
+            VideoInput::Digital {
+                interface: None,
+                bit_depth: None
+            }
+        ));
+        let tv = Edid::parse(TV.to_vec()).unwrap();
+        assert!(matches!(
+            tv.video_input(),
+            VideoInput::Digital {
+                interface: Some("HDMI-a"),
+                bit_depth: Some(8)
+            }
+        ));
+        let mut bytes = MONITOR.to_vec();
+        bytes[20] = 0x0e;
+        assert!(matches!(Edid::parse(bytes).unwrap().video_input(), VideoInput::Analog));
+    }
+
+    #[test]
+    fn chromaticity() {
+        let c = Edid::parse(MONITOR.to_vec()).unwrap().chromaticity();
+        assert_eq!(round3(c.red), (0.640, 0.330));
+        assert_eq!(round3(c.green), (0.300, 0.600));
+        assert_eq!(round3(c.blue), (0.150, 0.060));
+        assert_eq!(round3(c.white), (0.313, 0.329));
+    }
+
+    #[test]
+    fn timings() {
+        let monitor = Edid::parse(MONITOR.to_vec()).unwrap();
+        assert_eq!(
+            monitor.standard_timings(),
+            [(1920, 1200), (1680, 1050), (1280, 1024), (1280, 960), (1152, 864)]
+        );
+        assert_eq!(monitor.detailed_timings(), [(1920, 1200)]);
+        assert_eq!(monitor.max_resolution(), Some((1920, 1200)));
+        let tv = Edid::parse(TV.to_vec()).unwrap();
+        assert_eq!(
+            tv.standard_timings(),
+            [
+                (1152, 864),
+                (1280, 720),
+                (1280, 800),
+                (1280, 1024),
+                (1440, 900),
+                (1600, 900),
+                (1680, 1050)
+            ]
+        );
+        // Base block timings, then the CTA-861 one
+        assert_eq!(tv.detailed_timings(), [(3840, 2160), (1920, 1080), (1280, 720)]);
+        assert_eq!(tv.max_resolution(), Some((3840, 2160)));
+    }
+
+    #[test]
+    fn vrr_range() {
+        // A fixed rate 1.3 monitor, despite its 56-76Hz range limits
+        assert_eq!(Edid::parse(MONITOR.to_vec()).unwrap().vrr_range(), None);
+        assert_eq!(Edid::parse(TV.to_vec()).unwrap().vrr_range(), Some((48, 120)));
+        // Without FreeSync support, the range limits only count with continuous frequencies
+        let mut bytes = TV.to_vec();
+        // CTA header, video, audio, speaker and HDMI blocks, then the AMD tag, OUI and version
+        let flags = 128 + 4 + 6 + 7 + 4 + 6 + 1 + 4;
+        bytes[flags] &= !1;
+        assert_eq!(Edid::parse(bytes.clone()).unwrap().vrr_range(), None);
+        bytes[24] |= 1;
+        assert_eq!(Edid::parse(bytes).unwrap().vrr_range(), Some((24, 75)));
+    }
+
+    #[test]
+    fn detailed_modes() {
+        let modes = Edid::parse(MONITOR.to_vec()).unwrap().modes();
+        assert_eq!(
+            modes,
+            [Mode {
+                clock: 154000,
+                hdisplay: 1920,
+                hsync_start: 1968,
+                hsync_end: 2000,
+                htotal: 2080,
+                vdisplay: 1200,
+                vsync_start: 1203,
+                vsync_end: 1209,
+                vtotal: 1235,
+                vrefresh: 60,
+                flags: mode::FLAG_PHSYNC | mode::FLAG_NVSYNC,
+                preferred: true,
+            }]
+        );
+        let modes = Edid::parse(TV.to_vec()).unwrap().modes();
+        let sizes: Vec<_> = modes.iter().map(|m| (m.size(), m.clock, m.vrefresh)).collect();
+        assert_eq!(
+            sizes,
+            [((3840, 2160), 594000, 60), ((1920, 1080), 148500, 60), ((1280, 720), 74250, 60)]
+        );
+        assert!(modes[0].preferred && !modes[1].preferred);
+        assert_eq!((modes[1].hsync_start, modes[1].hsync_end, modes[1].htotal), (2008, 2052, 2200));
+        assert_eq!((modes[1].vsync_start, modes[1].vsync_end, modes[1].vtotal), (1084, 1089, 1125));
+        assert_eq!(modes[1].flags, mode::FLAG_PHSYNC | mode::FLAG_PVSYNC);
+    }
+
+    #[test]
+    fn audio_formats() {
+        assert!(Edid::parse(MONITOR.to_vec()).unwrap().audio_formats().is_empty());
+        let formats = Edid::parse(TV.to_vec()).unwrap().audio_formats();
+        let formats: Vec<_> = formats.iter().map(|f| (f.format, f.channels)).collect();
+        assert_eq!(formats, [("PCM", 2), ("AC-3", 6)]);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Display filter expressions, e.g. `width>=1920 && refresh>=60 || type==HDMI`
+//!
+//! An expression is comparisons joined by `&&` and `||`, with `&&` binding tighter. There are
+//! no parentheses.
+
+use crate::{refresh_rate, Display};
+
+/// Display property a filter compares
+#[derive(Clone, Copy)]
+enum Field {
+    Width,
+    Height,
+    Refresh,
+    /// Connector name up to the first dash, e.g. HDMI for HDMI-A-1
+    Type,
+    Connector,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+/// A single `field op value` comparison
+struct Comparison {
+    field: Field,
+    op: Op,
+    value: Value,
+}
+
+/// A parsed filter expression, as alternatives of comparisons that must all match
+pub struct Filter(Vec<Vec<Comparison>>);
+
+/// Operators, longest first so `>=` isn't read as `>`
+const OPERATORS: [&str; 8] = ["&&", "||", "==", "!=", ">=", "<=", ">", "<"];
+
+/// Split an expression into words and operators
+fn tokenize(expr: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() {
+        let len = match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            Some(op) => op.len(),
+            None => rest
+                .find(|c: char| c.is_whitespace() || "=!<>&|".contains(c))
+                .unwrap_or(rest.len())
+                .max(1),
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+impl std::str::FromStr for Filter {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(s).into_iter().peekable();
+        if tokens.peek().is_none() {
+            return Err(eyre::eyre!("empty filter"));
+        }
+        let mut alternatives = vec![vec![]];
+        loop {
+            let comparison = parse_comparison(&mut tokens)?;
+            alternatives.last_mut().unwrap().push(comparison);
+            let joiner = match tokens.next() {
+                None => break,
+                Some(joiner @ ("&&" | "||")) => joiner,
+                Some(token) => {
+                    return Err(eyre::eyre!("unexpected '{}' in filter, expected && or ||", token))
+                }
+            };
+            if tokens.peek().is_none() {
+                return Err(eyre::eyre!("filter ends with '{}'", joiner));
+            }
+            if joiner == "||" {
+                alternatives.push(vec![]);
+            }
+        }
+        Ok(Filter(alternatives))
+    }
+}
+
+/// Parse a `field op value` comparison
+fn parse_comparison<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> eyre::Result<Comparison> {
+    let name = tokens.next().ok_or_else(|| eyre::eyre!("missing comparison in filter"))?;
+    let field = match name {
+        "width" => Field::Width,
+        "height" => Field::Height,
+        "refresh" => Field::Refresh,
+        "type" => Field::Type,
+        "connector" => Field::Connector,
+        _ => return Err(eyre::eyre!("unknown field '{}' in filter", name)),
+    };
+    let op = match tokens.next() {
+        Some("==") => Op::Eq,
+        Some("!=") => Op::Ne,
+        Some(">=") => Op::Ge,
+        Some("<=") => Op::Le,
+        Some(">") => Op::Gt,
+        Some("<") => Op::Lt,
+        Some(token) => return Err(eyre::eyre!("unexpected '{}' in filter after {}", token, name)),
+        None => return Err(eyre::eyre!("missing operator after '{}' in filter", name)),
+    };
+    let value = tokens
+        .next()
+        .filter(|t| !OPERATORS.contains(t))
+        .ok_or_else(|| eyre::eyre!("missing value after '{}' in filter", name))?;
+    let value = match field {
+        Field::Width | Field::Height | Field::Refresh => Value::Number(
+            value
+                .parse()
+                .map_err(|_| eyre::eyre!("invalid number '{}' in filter", value))?,
+        ),
+        Field::Type | Field::Connector if op == Op::Eq || op == Op::Ne => {
+            Value::Text(value.to_string())
+        }
+        Field::Type | Field::Connector => {
+            return Err(eyre::eyre!("'{}' can only be compared with == and !=", name))
+        }
+    };
+    Ok(Comparison { field, op, value })
+}
+
+impl Comparison {
+    fn matches(&self, display: &Display) -> bool {
+        let (x, y) = display.mode.size();
+        let actual = match self.field {
+            Field::Width => Value::Number(x.into()),
+            Field::Height => Value::Number(y.into()),
+            Field::Refresh => Value::Number(refresh_rate(&display.mode)),
+            Field::Type => {
+                let kind = display.connector.split('-').next().unwrap_or_default();
+                Value::Text(kind.to_string())
+            }
+            Field::Connector => Value::Text(display.connector.clone()),
+        };
+        let order = match (&actual, &self.value) {
+            (Value::Number(a), Value::Number(b)) => a.total_cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            _ => return false,
+        };
+        match self.op {
+            Op::Eq => order.is_eq(),
+            Op::Ne => order.is_ne(),
+            Op::Ge => order.is_ge(),
+            Op::Le => order.is_le(),
+            Op::Gt => order.is_gt(),
+            Op::Lt => order.is_lt(),
+        }
+    }
+}
+
+impl Filter {
+    /// Normalize the connector names compared against, for `--normalize-names`
+    pub fn normalize_names(&mut self) {
+        for comparison in self.0.iter_mut().flatten() {
+            if let Field::Connector = comparison.field {
+                if let Value::Text(name) = &mut comparison.value {
+                    *name = crate::names::normalize(name);
+                }
+            }
+        }
+    }
+
+    /// Whether a display matches the expression
+    pub fn matches(&self, display: &Display) -> bool {
+        self.0.iter().any(|all| all.iter().all(|c| c.matches(display)))
+    }
+}
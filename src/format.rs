@@ -0,0 +1,144 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Machine readable output formats
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// A machine readable output format
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Csv,
+    /// Prometheus text exposition format, see `write_prometheus`
+    Prometheus,
+}
+
+/// Display fields shared by every machine readable format
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub index: usize,
+    /// Card device node name, e.g. card0
+    #[serde(default)]
+    pub card: String,
+    pub connector: String,
+    pub width: u32,
+    pub height: u32,
+    pub refresh: f64,
+    /// Raw EDID, base64 encoded, only in JSON and with `--with-edid`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid_base64: Option<String>,
+}
+
+/// Top level JSON document
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    pub displays: Vec<Record>,
+}
+
+/// Write records in the given format
+pub fn write(format: Format, records: Vec<Record>, out: &mut dyn Write) -> eyre::Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &Document { displays: records })?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "index,card,connector,width,height,refresh")?;
+            for r in records {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{:.2}",
+                    r.index, r.card, r.connector, r.width, r.height, r.refresh
+                )?;
+            }
+        }
+        Format::Prometheus => write_prometheus(&records, out)?,
+    }
+    Ok(())
+}
+
+/// Compare two documents, one line per changed display
+///
+/// Displays are matched by card and connector name, as two cards can both have a `DP-1`. Lines
+/// name the card too, unless the snapshot predates it. Unchanged displays are left out.
+pub fn diff(before: &Document, after: &Document) -> Vec<String> {
+    let mode = |r: &Record| {
+        format!("{}x{}@{}", r.width, r.height, crate::format_refresh(r.refresh))
+    };
+    let name = |r: &Record| match r.card.as_str() {
+        "" => r.connector.clone(),
+        card => format!("{} {}", card, r.connector),
+    };
+    let find = |doc: &Document, record: &Record| {
+        doc.displays
+            .iter()
+            .find(|r| r.card == record.card && r.connector == record.connector)
+            .map(mode)
+    };
+    let mut lines = vec![];
+    for old in &before.displays {
+        match find(after, old) {
+            Some(new) if new != mode(old) => {
+                lines.push(format!("{}: {} -> {}", name(old), mode(old), new))
+            }
+            Some(_) => (),
+            None => lines.push(format!("{}: removed ({})", name(old), mode(old))),
+        }
+    }
+    for new in &after.displays {
+        if find(before, new).is_none() {
+            lines.push(format!("{}: added ({})", name(new), mode(new)));
+        }
+    }
+    lines
+}
+
+/// Name, help text and value of a Prometheus metric
+type Metric = (&'static str, &'static str, fn(&Record) -> f64);
+
+/// Write records as Prometheus gauges, for the node exporter textfile collector
+///
+/// Every metric has `card` and `connector` labels, as connector names are only unique per card:
+/// * `rres_display_connected`: always 1, as only connected displays are listed
+/// * `rres_display_width` and `rres_display_height`: current resolution, in pixels
+/// * `rres_display_refresh_hz`: current refresh rate
+fn write_prometheus(records: &[Record], out: &mut dyn Write) -> eyre::Result<()> {
+    let metrics: [Metric; 4] = [
+        ("connected", "Whether the display is connected", |_| 1.0),
+        ("width", "Current horizontal resolution in pixels", |r| r.width.into()),
+        ("height", "Current vertical resolution in pixels", |r| r.height.into()),
+        ("refresh_hz", "Current refresh rate in Hz", |r| r.refresh),
+    ];
+    for (name, help, value) in metrics {
+        writeln!(out, "# HELP rres_display_{} {}", name, help)?;
+        writeln!(out, "# TYPE rres_display_{} gauge", name)?;
+        for r in records {
+            writeln!(
+                out,
+                "rres_display_{}{{card=\"{}\",connector=\"{}\"}} {}",
+                name,
+                r.card,
+                r.connector,
+                value(r)
+            )?;
+        }
+    }
+    Ok(())
+}
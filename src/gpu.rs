@@ -0,0 +1,534 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Display scanning through Linux DRM
+
+use std::fs;
+use std::path;
+use std::time::Duration;
+
+use drm::control::{self, connector, crtc, property, Device as ControlDevice, ResourceHandle};
+use drm::Device;
+use eyre::WrapErr;
+
+use crate::mode::Mode;
+use crate::{edid, names, sysfs, CardInfo, Display, HdrOutput, ScanOptions, Vblank};
+
+// Card handle
+// Really just to get a raw file descriptor for `drm`
+pub struct Card(fs::File);
+
+impl std::os::unix::io::AsRawFd for Card {
+    fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Card {
+    pub fn open<P: AsRef<path::Path>>(path: P) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        let mut options = fs::OpenOptions::new();
+        options.read(true);
+        options.write(true);
+        let file = options
+            .open(path)
+            .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+        Ok(Card(file))
+    }
+}
+
+// Implement `drm` types
+impl Device for Card {}
+impl ControlDevice for Card {}
+
+/// Largest width or height of a real mode
+const MAX_MODE_SIZE: u16 = 16384;
+
+impl From<control::Mode> for Mode {
+    fn from(mode: control::Mode) -> Self {
+        // `Mode::mode_type()` reads the wrong field in drm 0.6, use the raw mode info instead
+        let info = drm_ffi::drm_mode_modeinfo::from(mode);
+        Mode {
+            clock: info.clock,
+            hdisplay: info.hdisplay,
+            hsync_start: info.hsync_start,
+            hsync_end: info.hsync_end,
+            htotal: info.htotal,
+            vdisplay: info.vdisplay,
+            vsync_start: info.vsync_start,
+            vsync_end: info.vsync_end,
+            vtotal: info.vtotal,
+            vrefresh: info.vrefresh,
+            flags: info.flags,
+            preferred: info.type_ & drm_ffi::DRM_MODE_TYPE_PREFERRED != 0,
+        }
+    }
+}
+
+/// Find the card device nodes, or check the one picked by hand (e.g. card0)
+///
+/// The cards are sorted (card0, card1, card2...).
+pub fn find_cards(card: Option<&str>) -> eyre::Result<Vec<path::PathBuf>> {
+    let mut cards: Vec<path::PathBuf> = vec![];
+    if let Some(c) = card {
+        // Open single card
+        let mut file = path::PathBuf::from("/dev/dri/");
+        file.push(c);
+        if !file.exists() || !c.starts_with("card") {
+            return Err(eyre::eyre!("invalid card ({})", c));
+        }
+        cards.push(file);
+    } else {
+        // Open every card on the system
+        for entry in fs::read_dir("/dev/dri/").wrap_err("failed to read /dev/dri")? {
+            let file = entry?;
+
+            if let Some(name) = file.file_name().to_str() {
+                if name.starts_with("card") {
+                    cards.push(file.path());
+                }
+            }
+        }
+    }
+    cards.sort();
+    Ok(cards)
+}
+
+/// Check that a card can be opened
+pub fn probe(file: &path::Path) -> eyre::Result<()> {
+    Card::open(file).map(|_| ())
+}
+
+/// Get the driver and connector counts of a card
+pub fn card_info(file: &path::Path) -> eyre::Result<CardInfo> {
+    let gpu = Card::open(file)?;
+    let driver = gpu.get_driver()?.name().to_string_lossy().into_owned();
+    let (connected, total) = count_connectors(&gpu)?;
+    Ok(CardInfo {
+        driver,
+        connected,
+        total,
+    })
+}
+
+/// Driver capabilities from `DRM_IOCTL_GET_CAP`, by kernel name
+const DRIVER_CAPS: [(&str, u64); 14] = [
+    ("DUMB_BUFFER", drm_ffi::DRM_CAP_DUMB_BUFFER as u64),
+    ("VBLANK_HIGH_CRTC", drm_ffi::DRM_CAP_VBLANK_HIGH_CRTC as u64),
+    ("DUMB_PREFERRED_DEPTH", drm_ffi::DRM_CAP_DUMB_PREFERRED_DEPTH as u64),
+    ("DUMB_PREFER_SHADOW", drm_ffi::DRM_CAP_DUMB_PREFER_SHADOW as u64),
+    ("PRIME", drm_ffi::DRM_CAP_PRIME as u64),
+    ("TIMESTAMP_MONOTONIC", drm_ffi::DRM_CAP_TIMESTAMP_MONOTONIC as u64),
+    ("ASYNC_PAGE_FLIP", drm_ffi::DRM_CAP_ASYNC_PAGE_FLIP as u64),
+    ("CURSOR_WIDTH", drm_ffi::DRM_CAP_CURSOR_WIDTH as u64),
+    ("CURSOR_HEIGHT", drm_ffi::DRM_CAP_CURSOR_HEIGHT as u64),
+    ("ADDFB2_MODIFIERS", drm_ffi::DRM_CAP_ADDFB2_MODIFIERS as u64),
+    ("PAGE_FLIP_TARGET", drm_ffi::DRM_CAP_PAGE_FLIP_TARGET as u64),
+    ("CRTC_IN_VBLANK_EVENT", drm_ffi::DRM_CAP_CRTC_IN_VBLANK_EVENT as u64),
+    ("SYNCOBJ", drm_ffi::DRM_CAP_SYNCOBJ as u64),
+    ("ATOMIC_ASYNC_PAGE_FLIP", DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP),
+];
+
+/// Not known to drm 0.6
+const DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP: u64 = 0x15;
+
+/// Get the capabilities advertised by the driver of a card
+///
+/// Capabilities the driver doesn't know of (older kernels) have no value.
+pub fn driver_caps(file: &path::Path) -> eyre::Result<Vec<(&'static str, Option<u64>)>> {
+    use std::os::unix::io::AsRawFd;
+    let gpu = Card::open(file)?;
+    let caps = DRIVER_CAPS
+        .iter()
+        .map(|(name, cap)| {
+            let value = drm_ffi::get_capability(gpu.as_raw_fd(), *cap).ok().map(|c| c.value);
+            (*name, value)
+        })
+        .collect();
+    Ok(caps)
+}
+
+/// Read the displays connected to a card
+///
+/// Failures past opening the card are logged, and give no displays.
+pub fn read_card(file: &path::Path, scan: &ScanOptions) -> eyre::Result<Vec<Display>> {
+    let gpu = Card::open(file)?;
+    let info = gpu.get_driver()?;
+    log::info!("Found GPU: {}", info.name().to_string_lossy());
+    let card = file.file_name().unwrap_or_default().to_string_lossy();
+    // Find displays
+    match get_card_modes(gpu, &card, scan) {
+        Ok(displays) => Ok(displays),
+        Err(e) => {
+            log::error!("failed to read modes: {}", e);
+            Ok(vec![])
+        }
+    }
+}
+
+/// Get all the connected display's modes from a libdrm card.
+///
+/// `card` is the card's device node name. Connectors excluded by `scan` are skipped entirely.
+pub fn get_card_modes<G: ControlDevice>(
+    gpu: G,
+    card: &str,
+    scan: &ScanOptions,
+) -> eyre::Result<Vec<Display>> {
+    let mut displays: Vec<Display> = vec![];
+
+    let resources = gpu.resource_handles().wrap_err("failed to get resource handles")?;
+    let tearing = get_tearing_support(&gpu);
+    let connectors = resources.connectors();
+    for handle in connectors {
+        let connector = gpu.get_connector(*handle).wrap_err("failed to get connector handle")?;
+        if connector.state() == connector::State::Connected {
+            let kernel_name = connector_name(&connector);
+            let name = if scan.normalize_names {
+                names::normalize(&kernel_name)
+            } else {
+                kernel_name.clone()
+            };
+            if scan.exclude.contains(&name) {
+                log::info!("Skipping excluded connector {}", name);
+                continue;
+            }
+            let edid_override = scan.edid_overrides.get(&name);
+            // An override is the fix for a short mode list
+            if edid_override.is_none() && connector.modes().len() < scan.min_modes {
+                log::warn!(
+                    "Skipping connector {}: only {} modes",
+                    name,
+                    connector.modes().len()
+                );
+                continue;
+            }
+            // The modes of an EDID override are its detailed timings
+            let modes = match edid_override.map(edid::Edid::modes) {
+                Some(modes) if !modes.is_empty() => modes,
+                _ => connector.modes().iter().map(|m| Mode::from(*m)).collect(),
+            };
+            let modes: Vec<Mode> = modes
+                .into_iter()
+                .filter(|m| scan.keep_phantom || !is_phantom(m, &name))
+                .collect();
+            // Connected, get mode
+            let crtc = get_connector_crtc(&gpu, &connector)?;
+            let current = crtc
+                .and_then(|c| c.mode())
+                .map(Mode::from)
+                .filter(|m| scan.keep_phantom || !is_phantom(m, &name));
+            // A lit crtc still gives a mode when every listed one is phantom, or there's none
+            let native = match get_native_mode(&modes).or(current) {
+                Some(native) => native,
+                None => {
+                    log::warn!("Skipping connector {}: no usable mode", name);
+                    continue;
+                }
+            };
+            let mode = get_connector_mode(&connector, current, native)?;
+            let scanout = get_scanout_size(&gpu, crtc.as_ref());
+            let internal = is_internal(connector.interface());
+            let (edid, edid_source) = match edid_override {
+                Some(edid) => (Some(edid.clone()), edid::Source::Overridden),
+                None => match get_edid(&gpu, &connector) {
+                    Some(edid) => (Some(edid), sysfs::edid_source(card, &kernel_name)),
+                    None => (None, edid::Source::Unknown),
+                },
+            };
+            let hdr = get_hdr_output(&gpu, &connector);
+            let vblank = match crtc.as_ref() {
+                Some(c) if scan.vblank => get_vblank(&gpu, resources.crtcs(), c.handle()),
+                _ => None,
+            };
+            displays.push(Display {
+                card: card.to_string(),
+                connector: name,
+                internal,
+                mode,
+                native,
+                position: crtc.as_ref().map(|c| c.position()),
+                scanout,
+                modes,
+                edid,
+                edid_source,
+                tearing,
+                hdr,
+                vblank,
+            });
+        }
+    }
+    Ok(displays)
+}
+
+/// Check whether a card allows async (tearing) page flips
+///
+/// This is a driver capability, shared by every crtc of the card. Both the legacy and the
+/// atomic capabilities are checked.
+fn get_tearing_support<G: ControlDevice>(gpu: &G) -> Option<bool> {
+    let legacy = gpu.get_driver_capability(drm::DriverCapability::ASyncPageFlip);
+    let atomic = drm_ffi::get_capability(gpu.as_raw_fd(), DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP);
+    match (legacy, atomic) {
+        (Err(_), Err(_)) => None,
+        (legacy, atomic) => {
+            Some(legacy.is_ok_and(|v| v != 0) || atomic.is_ok_and(|c| c.value != 0))
+        }
+    }
+}
+
+/// Check for modes with impossible dimensions, exposed by some drivers
+fn is_phantom(mode: &Mode, connector: &str) -> bool {
+    let (x, y) = mode.size();
+    let phantom = x == 0 || y == 0 || x > MAX_MODE_SIZE || y > MAX_MODE_SIZE;
+    if phantom {
+        log::debug!("Discarding phantom mode {}x{} of {}", x, y, connector);
+    }
+    phantom
+}
+
+/// Count the connected connectors of a libdrm card, along with the total number of connectors.
+fn count_connectors<G: ControlDevice>(gpu: &G) -> eyre::Result<(usize, usize)> {
+    let resources = gpu.resource_handles().wrap_err("failed to get resource handles")?;
+    let mut connected = 0;
+    for handle in resources.connectors() {
+        let connector = gpu.get_connector(*handle).wrap_err("failed to get connector handle")?;
+        if connector.state() == connector::State::Connected {
+            connected += 1;
+        }
+    }
+    Ok((connected, resources.connectors().len()))
+}
+
+/// Get the kernel name of a connector, e.g. HDMI-A-1
+fn connector_name(connector: &connector::Info) -> String {
+    use drm::control::connector::Interface::*;
+    let kind = match connector.interface() {
+        Unknown => "Unknown",
+        VGA => "VGA",
+        DVII => "DVI-I",
+        DVID => "DVI-D",
+        DVIA => "DVI-A",
+        Composite => "Composite",
+        SVideo => "SVIDEO",
+        LVDS => "LVDS",
+        Component => "Component",
+        NinePinDIN => "DIN",
+        DisplayPort => "DP",
+        HDMIA => "HDMI-A",
+        HDMIB => "HDMI-B",
+        TV => "TV",
+        EmbeddedDisplayPort => "eDP",
+        Virtual => "Virtual",
+        DSI => "DSI",
+        DPI => "DPI",
+    };
+    format!("{}-{}", kind, connector.interface_id())
+}
+
+/// Get the raw value of a DRM object's property by name
+///
+/// Returns `None` when the object has no such property.
+fn get_property<G: ControlDevice, T: ResourceHandle>(
+    gpu: &G,
+    handle: T,
+    name: &str,
+) -> eyre::Result<Option<(property::Info, property::RawValue)>> {
+    let props = gpu.get_properties(handle).wrap_err("failed to get properties")?;
+    let (ids, values) = props.as_props_and_values();
+    for (id, value) in ids.iter().zip(values) {
+        let info = gpu.get_property(*id).wrap_err("failed to get property")?;
+        if info.name().to_bytes() == name.as_bytes() {
+            return Ok(Some((info, *value)));
+        }
+    }
+    Ok(None)
+}
+
+/// Read and parse the EDID of a connector
+///
+/// Errors are logged, as the EDID is only needed by some reports.
+fn get_edid<G: ControlDevice>(
+    gpu: &G,
+    connector: &connector::Info,
+) -> Option<edid::Edid> {
+    let blob = match get_property(gpu, connector.handle(), "EDID") {
+        Ok(Some((_, blob))) if blob != 0 => blob,
+        Ok(_) => return None,
+        Err(e) => {
+            log::debug!("failed to find EDID property: {}", e);
+            return None;
+        }
+    };
+    match gpu.get_property_blob(blob).map_err(eyre::Report::from).and_then(edid::Edid::parse) {
+        Ok(edid) => Some(edid),
+        Err(e) => {
+            log::warn!("failed to read EDID of {}: {}", connector_name(connector), e);
+            None
+        }
+    }
+}
+
+/// Read the HDR metadata a connector is sending
+///
+/// Returns `None` when the connector has no `HDR_OUTPUT_METADATA` property.
+fn get_hdr_output<G: ControlDevice>(
+    gpu: &G,
+    connector: &connector::Info,
+) -> Option<HdrOutput> {
+    let blob = match get_property(gpu, connector.handle(), "HDR_OUTPUT_METADATA") {
+        Ok(Some((_, 0))) => return Some(HdrOutput::Inactive),
+        Ok(Some((_, blob))) => blob,
+        Ok(None) => return None,
+        Err(e) => {
+            log::debug!("failed to find HDR_OUTPUT_METADATA property: {}", e);
+            return None;
+        }
+    };
+    let data = match gpu.get_property_blob(blob) {
+        Ok(data) => data,
+        Err(e) => {
+            log::warn!("failed to read HDR metadata of {}: {}", connector_name(connector), e);
+            return None;
+        }
+    };
+    // struct hdr_output_metadata: a u32 type, then the infoframe starting with the EOTF
+    if data.len() < 30 {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_ne_bytes([data[i], data[i + 1]]);
+    match data[4] {
+        // Traditional SDR gamma
+        0 => Some(HdrOutput::Inactive),
+        eotf => Some(HdrOutput::Active {
+            eotf,
+            max_cll: u16_at(26),
+            max_fall: u16_at(28),
+        }),
+    }
+}
+
+/// Get the CRTC currently driving a connector
+///
+/// Returns `None` when the connector has no encoder or crtc attached.
+fn get_connector_crtc<G: ControlDevice>(
+    gpu: &G,
+    connector: &connector::Info,
+) -> eyre::Result<Option<crtc::Info>> {
+    if let Some(encoder_handle) = connector.current_encoder() {
+        // Get the encoder then crtc
+        let encoder = gpu.get_encoder(encoder_handle)?;
+        if let Some(crtc_handle) = encoder.crtc() {
+            let crtc = gpu.get_crtc(crtc_handle).wrap_err("failed to get crtc")?;
+            return Ok(Some(crtc));
+        }
+    }
+    Ok(None)
+}
+
+/// Read the current vblank counter of a CRTC
+///
+/// The vblank ioctl takes the index of the CRTC rather than its handle. Returns `None` when the
+/// driver refuses, e.g. because the CRTC is off or the driver has no vblank support.
+fn get_vblank<G: ControlDevice>(
+    gpu: &G,
+    crtcs: &[crtc::Handle],
+    crtc: crtc::Handle,
+) -> Option<Vblank> {
+    use drm_ffi::drm_vblank_seq_type::*;
+    let pipe = crtcs.iter().position(|c| *c == crtc)? as u32;
+    // Waiting for 0 vblanks returns right away, with the current counter
+    let mut vbl = drm_ffi::drm_wait_vblank {
+        request: drm_ffi::drm_wait_vblank_request {
+            type_: _DRM_VBLANK_RELATIVE | ((pipe << 1) & _DRM_VBLANK_HIGH_CRTC_MASK),
+            sequence: 0,
+            signal: 0,
+        },
+    };
+    if let Err(e) = unsafe { drm_ffi::ioctl::wait_vblank(gpu.as_raw_fd(), &mut vbl) } {
+        log::debug!("failed to read vblank counter of crtc {}: {}", pipe, e);
+        return None;
+    }
+    // The kernel overwrites the request with the reply
+    let reply = unsafe { vbl.reply };
+    Some(Vblank {
+        sequence: reply.sequence,
+        timestamp: Duration::new(reply.tval_sec as u64, reply.tval_usec as u32 * 1000),
+    })
+}
+
+/// Get current display mode from connector
+///
+/// `current` is the mode of the connector's crtc, if any.
+///
+/// Note: nVidia GPUs don't share the current encoder+crtc, so this function will report the
+/// native display's resolution instead of the current resolution.
+fn get_connector_mode(
+    connector: &connector::Info,
+    current: Option<Mode>,
+    native: Mode,
+) -> eyre::Result<Mode> {
+    if connector.state() != connector::State::Connected {
+        return Err(eyre::eyre!("Connector is disconnected"));
+    }
+    // Get current mode, and store it
+    if let Some(current_mode) = current {
+        log::info!(
+            "Found display: {:?}, {}x{}",
+            connector.interface(),
+            current_mode.size().0,
+            current_mode.size().1
+        );
+        return Ok(current_mode);
+    }
+    // nVidia GPUs don't expose the encoder (and thus neither the crtc)
+    log::warn!(
+        "Could not detect current mode for display {:?},",
+        connector.interface()
+    );
+    log::warn!("reading native resolution");
+    Ok(native)
+}
+
+/// Get the size of the framebuffer being scanned out by a crtc
+///
+/// This can differ from the crtc mode when the compositor scales the primary plane.
+fn get_scanout_size<G: ControlDevice>(
+    gpu: &G,
+    crtc: Option<&crtc::Info>,
+) -> Option<(u32, u32)> {
+    let handle = crtc?.framebuffer()?;
+    match gpu.get_framebuffer(handle) {
+        Ok(fb) => Some(fb.size()),
+        Err(e) => {
+            log::debug!("failed to get framebuffer: {}", e);
+            None
+        }
+    }
+}
+
+/// Get the native display mode from connector
+///
+/// This is the mode flagged as preferred by the driver, or the first of `modes` if none is.
+/// Returns `None` when `modes` is empty.
+fn get_native_mode(modes: &[Mode]) -> Option<Mode> {
+    modes.iter().find(|m| m.preferred).or_else(|| modes.first()).copied()
+}
+
+/// Whether a connector type is used for built-in panels, like a laptop screen
+fn is_internal(interface: connector::Interface) -> bool {
+    use connector::Interface::*;
+    matches!(interface, EmbeddedDisplayPort | LVDS | DSI | DPI)
+}
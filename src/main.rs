@@ -15,24 +15,200 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::io::{IsTerminal, Write};
 use std::path;
 use std::process;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use drm::control::{Device as ControlDevice, Mode};
-use drm::Device;
+use base64::Engine;
 use eyre::WrapErr;
+use sha2::{Digest, Sha256};
 use simple_logger::SimpleLogger;
 
+#[cfg(feature = "dbus")]
+mod dbus;
+mod edid;
+mod filter;
+mod format;
+mod mode;
+mod names;
+mod sysfs;
+
+// Only Linux has DRM, other platforms build with a backend that always fails
+#[cfg(target_os = "linux")]
+mod gpu;
+#[cfg(not(target_os = "linux"))]
+#[path = "unsupported.rs"]
+mod gpu;
+
+use mode::Mode;
+
 const USAGE: &str = "\
 Usage: rres [options]
 
   -c, --card <card>\tSpecify a GPU (file existing in /dev/dri/, eg. card0)
+  --seat <seat>\t\tOnly read the GPUs of a logind seat. Defaults to the
+               \t\tcurrent seat ($XDG_SEAT) unless --card is used
+  -i, --interactive\tPick the display from a menu (requires a terminal)
+  --at <x>,<y>\t\tSelect the display covering this point of the desktop
+  --highest-dpi\t\tSelect the display with the highest DPI, from the EDID
+               \t\tphysical size. Displays without one are skipped
+  --simple\t\tPrint only WxH of the display most users mean: a lit one
+          \t\t(driven by a CRTC), then the primary one (at 0,0), then an
+          \t\texternal one, then the largest
+  --list-cards\t\tList every GPU with its driver and connected display count
+  --connector-stats\tPrint the connected and total connector count of every GPU,
+                   \te.g. card0: 2/6
+  --caps\t\t\tPrint the DRM capabilities of every GPU's driver (e.g.
+        \t\t\tDUMB_BUFFER, TIMESTAMP_MONOTONIC) and the client
+        \t\t\tcapabilities rres enables, for bug reports
+  --list-modes\t\tList every mode of each display
+  --max\t\t\tReport the largest mode of each display
+  --closest <W>x<H>\tReport the mode closest to this size
+  --prefer-refresh <hz>\tWith --max and --closest, pick the refresh rate closest to
+                       \tthis among the modes of the picked size, instead of the
+                       \thighest one. It never changes the picked size
+  --set-refresh <hz>\tReport the mode with this refresh rate at the current
+                    \tresolution, and fail if there's none
+  --refresh-tolerance <hz>
+                \t\tHow far from the --set-refresh rate a mode can be, 0.5
+                \t\tby default so 60 matches 59.94
+  --refresh-filter <integer|fractional>
+                \t\tOnly list or pick modes with whole (60Hz) or NTSC style
+                \t\t(59.94Hz) refresh rates. A rate is whole when it's
+                \t\twithin 0.05% of an integer
+  --min-width <px>\tOnly list or pick modes at least this wide
+  --min-refresh <hz>\tOnly list or pick modes with at least this refresh rate
+  --aspect-filter <w:h>\tOnly list or pick modes of this aspect ratio, e.g. 16:9,
+                       \twithin 3% so 3440x1440 counts as 21:9
+  --json\t\tPrint every display as JSON
+  --csv\t\t\tPrint every display as CSV
+  --with-edid\t\tInclude the raw EDID in the JSON output, base64 encoded
+             \t\t(left out for displays without one)
+  --prometheus\t\tPrint every display as Prometheus metrics
+  --json-file <path>\tWrite every display as JSON to a file (- for stdout)
+  --csv-file <path>\tWrite every display as CSV to a file (- for stdout)
+  --prometheus-file <path>
+                   \tWrite every display as Prometheus metrics to a file
+                   \t(- for stdout). The regular output is omitted when
+                   \twriting to stdout
   -m, --multi\t\tRead all monitors. If this option is ommited, rres will
              \t\treturn the resolution of the first detected monitor
+  --expect <n>\t\tExit with code 3 unless exactly n displays are connected
+  --expect-single\tSame as --expect 1
+  --laptop-docked\tCheck if an external display is connected alongside the
+                 \tinternal panel, and print it. Exits with 0 when docked,
+                 \t1 with only the internal panel and 2 without one
+  --wine-best\t\tPrint the largest resolution that fits on every display,
+             \t\tfor a Wine virtual desktop (smallest width x smallest height)
+  --win32 <size|rect|desktop>
+                \t\tPrint in Win32 terms. size is W,H, the dmPelsWidth and
+                \t\tdmPelsHeight of a DEVMODE for ChangeDisplaySettings.
+                \t\trect is left,top,right,bottom, the rcMonitor RECT of
+                \t\tGetMonitorInfo. desktop is that RECT around every
+                \t\tdisplay, the virtual screen. Positions are desktop
+                \t\tones: Wine moves its primary display to 0,0
+  --with-native\t\tAlso print the native resolution when it differs from
+               \t\tthe current one, e.g. 1280x720 (native 1920x1080)
+  --frametime\t\tAlso print the refresh rate and frame time, e.g.
+             \t\t1920x1080@60 (16.67ms)
+  --edid-max\t\tReport the largest resolution advertised by the EDID
+            \t\t(and the mode list's largest when it differs)
+  --capability-report\tReport the panel (EDID) maximum, mode list maximum and
+                     \tcurrent mode of each display, and what limits it
+  --chromaticity\tReport the EDID color primaries and white point,
+                \tas R(x,y) G(x,y) B(x,y) W(x,y)
+  --id\t\t\tPrint a stable ID hashed from the EDID manufacturer, product
+      \t\t\tand serial (the connector name if there's no EDID)
+  --xrandr-cmd\t\tPrint an xrandr command setting the current mode
+  --tearing\t\tReport whether async (tearing) page flips are allowed
+  --cvt\t\t\tPrint a cvt command generating the current mode (cvt -r
+       \t\t\tif it uses reduced blanking)
+  --oneline\t\tPrint the index, connector, mode and monitor name of each
+           \t\tdisplay, e.g. #0 HDMI-A-1 1920x1080@60 \"DELL U2720Q\"
+  --kv\t\t\tPrint width=W height=H refresh=R connector=NAME for each display
+  --input-type\t\tReport whether the EDID input is digital (with its interface
+              \t\tand bit depth) or analog
+  --audio\t\tReport the audio formats supported by the display (from the
+         \t\tEDID), e.g. PCM 2ch, AC-3 6ch
+  --freesync-range\tReport the variable refresh rate range advertised by the
+                  \tEDID, e.g. 48-144Hz
+  --fbdev\t\tReport the framebuffer device (/dev/fbN) of the display's GPU,
+         \t\tor none
+  --edid-source\t\tReport whether the EDID is live (read from the display),
+               \t\toverridden or unknown. Telling live ones apart needs root,
+               \t\tto read the debugfs override
+  --hdr-active\t\tReport whether HDR is being output, with its transfer function
+              \t\tand content light levels, e.g. active (PQ, MaxCLL 1000)
+  --touch\t\tReport whether the display is a touchscreen (touch, no touch
+         \t\tor unknown), from the udev input devices
+  --vblank\t\tReport the vblank counter of the display's CRTC and the time of
+          \t\tthe last vblank in seconds on the monotonic clock, e.g.
+          \t\t123456 at 4567.891234s (unavailable if the driver refuses)
+  --fractional\t\tReport the scale factor from the scanned out framebuffer to
+              \t\tthe mode, with the logical size when scaled, e.g.
+              \t\t1.25x (2048x1152), or 1.0x. Unknown if none is attached,
+              \t\tor it spans several displays, as under X11
+  --scanout\t\tReport the size of the framebuffer being scanned out
+           \t\tinstead of the mode size (unknown if none is attached)
+  --exclude-connector <name>
+                \t\tSkip a connector (e.g. DP-2). Can be specified multiple
+                \t\ttimes. Excluded displays get no index, so the following
+                \t\tdisplays shift down by one
+  --min-modes <n>\tSkip displays with fewer than n modes, usually caused by
+                 \ta failed EDID read
+  --keep-phantom\tKeep modes with a zero or absurd (over 16384) size,
+                \twhich are discarded by default
+  --normalize-names\tReport connectors by canonical names portable across drivers
+                   \tand X11, e.g. HDMI-1 for HDMI-A-1, DP-1 for DisplayPort-1
+                   \tor DP1. Connector names given to other options are
+                   \tnormalized too
+  --sort <name|name-natural>
+                \t\tSort the displays by connector name. name-natural
+                \t\tcompares numbers by value, so DP-9 comes before DP-10
+  --filter <expr>\tOnly keep the displays matching an expression, like
+                 \twidth>=1920 && refresh>=60 || type==HDMI. Fields are
+                 \twidth, height, refresh, type (the connector name up to
+                 \tthe first dash) and connector; operators are ==, !=, >=,
+                 \t<=, > and <, with && binding tighter than ||. Filtered
+                 \tout displays get no index
+  --edid-override <connector>=<file>
+                \t\tRead the EDID of a connector from a file instead of the
+                \t\thardware. Its detailed timings replace the kernel's mode
+                \t\tlist (for --list-modes, --max...), its name and size
+                \t\tthe monitor name and DPI. Can be specified multiple times
   -v, --verbose\t\tVerbosity level. Can be specified multiple times, e.g. -vv
   -q, --quiet\t\tLower verbosity level. Opposite to -v
+  --no-logger\t\tDon't set up logging at all, so nothing gets logged
+  --diff <a> <b>\tCompare two --json snapshots, printing the displays that
+                \twere added, removed or changed modes. No GPU is read
+  -w, --watch <sec>\tRead the displays again every sec seconds, writing the
+                   \toutput after each scan
+  -o, --output-file <path>
+                   \tWrite the regular output to a file instead of stdout
+  --only-changed\tWhile watching, only write the outputs when they change
+  --mode-age\t\tWhile watching, also print how long the mode of each display
+            \t\thas been set (since rres started) to stderr
+  --wait-for-mode <W>x<H>[@<hz>]
+                \t\tWait until a display's current mode is this one, then
+                \t\texit with 0. The refresh rate is matched within
+                \t\t--refresh-tolerance. Exits with code 4 on timeout
+  --wait-connector <name>
+                \t\tWith --wait-for-mode, only watch this connector
+  --wait-timeout <sec>\tHow long --wait-for-mode waits, 30 seconds by default
+  --dbus\t\t\tServe the displays on the session bus as io.github.rokbma.rres,
+        \t\t\twith a GetDisplays method. With --watch, DisplaysChanged is
+        \t\t\tsent when they change. Both give the displays as a(ussuud):
+        \t\t\tindex, card, connector, width, height and refresh rate.
+        \t\t\tRequires the dbus build feature
+  -t, --timeout <sec>\tGive up if reading the displays (or the GPUs, with
+                     \t--list-cards, --connector-stats and --caps) takes longer
+                     \tthan this. The partial results are discarded
   -h, --help\t\tShow this help message
 
 Environment variables:
@@ -44,34 +220,379 @@ Wine Virtual Desktop example:
 
   wine \"explorer /desktop=Game,$(./rres)\" game.exe";
 
-// Card handle
-// Really just to get a raw file descriptor for `drm`
-pub struct Card(std::fs::File);
+/// A connected display
+pub struct Display {
+    /// Device node name of the card, e.g. card0
+    pub card: String,
+    /// Connector name, e.g. HDMI-A-1
+    pub connector: String,
+    /// Whether this is a built-in panel, like a laptop screen
+    pub internal: bool,
+    /// Current mode
+    pub mode: Mode,
+    /// Native (preferred) mode
+    pub native: Mode,
+    /// Position on the desktop of the CRTC driving the display, if any
+    pub position: Option<(u32, u32)>,
+    /// Size of the framebuffer being scanned out, if any
+    pub scanout: Option<(u32, u32)>,
+    /// Every mode reported by the connector
+    pub modes: Vec<Mode>,
+    /// Parsed EDID, if the connector exposes one
+    pub edid: Option<edid::Edid>,
+    /// Where the EDID comes from
+    pub edid_source: edid::Source,
+    /// Whether the card allows async (tearing) page flips, if known
+    pub tearing: Option<bool>,
+    /// HDR metadata being sent, if the connector supports it
+    pub hdr: Option<HdrOutput>,
+    /// Vblank counter of the CRTC, only read with `--vblank`
+    pub vblank: Option<Vblank>,
+}
+
+/// HDR output state of a connector, from its `HDR_OUTPUT_METADATA` property
+pub enum HdrOutput {
+    Inactive,
+    Active {
+        /// CTA-861-G EOTF, e.g. 2 for PQ
+        eotf: u8,
+        /// Maximum content light level in nits, 0 if unknown
+        max_cll: u16,
+        /// Maximum frame-average light level in nits, 0 if unknown
+        max_fall: u16,
+    },
+}
+
+/// Vblank counter of a CRTC, as returned by the DRM vblank ioctl
+pub struct Vblank {
+    /// Number of vblanks since the CRTC was enabled
+    pub sequence: u32,
+    /// Time of the last vblank, on the kernel's vblank clock (usually `CLOCK_MONOTONIC`)
+    pub timestamp: Duration,
+}
+
+/// Driver and connectors of a GPU
+pub struct CardInfo {
+    /// Kernel driver name, e.g. amdgpu
+    pub driver: String,
+    /// Number of connected connectors
+    pub connected: usize,
+    /// Number of connectors
+    pub total: usize,
+}
+
+/// Exit code when `--expect` doesn't match the display count
+const EXIT_UNEXPECTED_COUNT: i32 = 3;
+
+/// Exit code when `--wait-for-mode` times out
+const EXIT_WAIT_TIMEOUT: i32 = 4;
+
+/// Default `--wait-for-mode` timeout
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `--wait-for-mode` reads the displays
+const WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Hint for Flatpak sandboxes that don't expose the GPUs
+const FLATPAK_HINT: &str = "running in a Flatpak sandbox, it needs the --device=dri permission";
+
+/// Relative distance to the `--aspect-filter` ratio still matching it
+///
+/// Marketing ratios are rounded: 3440x1440 is 21:9 at 2.4% off, while 16:10 and 16:9 are 10%
+/// apart.
+const ASPECT_TOLERANCE: f64 = 0.03;
+
+/// Default maximum distance to the `--set-refresh` rate, in Hz
+const DEFAULT_REFRESH_TOLERANCE: f64 = 0.5;
+
+/// Mode to wait for with `--wait-for-mode`
+struct ModeTarget {
+    size: (u16, u16),
+    /// Refresh rate, any if `None`
+    refresh: Option<f64>,
+    /// Connector to watch, any if `None`
+    connector: Option<String>,
+}
+
+/// Display scanning settings
+#[derive(Default)]
+pub struct ScanOptions {
+    /// Connector names to skip
+    pub exclude: Vec<String>,
+    /// Skip connectors with fewer modes than this, as it's a sign of a bad EDID read
+    pub min_modes: usize,
+    /// Keep modes with impossible dimensions
+    pub keep_phantom: bool,
+    /// EDIDs to use instead of the ones read from the hardware, by connector name
+    pub edid_overrides: HashMap<String, edid::Edid>,
+    /// Sort the displays, instead of keeping the card and connector order
+    pub sort: Option<SortKey>,
+    /// Only keep the displays matching this expression
+    pub filter: Option<filter::Filter>,
+    /// Read the vblank counter of each CRTC, which briefly turns on its vblank interrupt
+    pub vblank: bool,
+    /// Report connectors by their canonical names, see `names`
+    pub normalize_names: bool,
+}
+
+/// Display sort orders
+#[derive(Clone, Copy)]
+pub enum SortKey {
+    /// Connector name, character by character
+    Name,
+    /// Connector name, with numbers compared by value (DP-9 before DP-10)
+    NameNatural,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "name-natural" => Ok(SortKey::NameNatural),
+            _ => Err(eyre::eyre!("invalid sort ({}), expected name or name-natural", s)),
+        }
+    }
+}
+
+/// What to print for each display
+#[derive(Default)]
+enum Report {
+    /// Current resolution
+    #[default]
+    Resolution,
+    /// Largest resolution advertised by the EDID
+    EdidMax,
+    /// Color primaries and white point from the EDID
+    Chromaticity,
+    /// `key=value` pairs
+    Kv,
+    /// Panel, mode list and current maxima
+    Capabilities,
+    /// Stable ID from the EDID identity
+    Id,
+    /// Every mode of the connector
+    Modes,
+    /// xrandr command setting the current mode
+    XrandrCmd,
+    /// Mode picked from the mode list
+    Select(ModeSelector),
+    /// Whether tearing page flips are allowed
+    Tearing,
+    /// EDID video input definition
+    InputType,
+    /// Audio formats from the EDID
+    Audio,
+    /// cvt command generating the current mode
+    Cvt,
+    /// Mode with a refresh rate at the current resolution
+    SetRefresh(f64),
+    /// Whether the display is a touchscreen
+    Touch,
+    /// Index, connector, mode and monitor name
+    Oneline,
+    /// Variable refresh rate range from the EDID
+    FreesyncRange,
+    /// Framebuffer device of the card
+    Fbdev,
+    /// HDR metadata being output
+    HdrActive,
+    /// Where the EDID comes from
+    EdidSource,
+    /// Win32 size or rectangle
+    Win32(Win32Format),
+    /// Vblank counter and timestamp of the CRTC
+    Vblank,
+    /// Scale factor between the scanout framebuffer and the mode
+    Fractional,
+}
+
+/// Win32 style outputs
+#[derive(Clone, Copy)]
+enum Win32Format {
+    /// `W,H` of each display
+    Size,
+    /// `left,top,right,bottom` of each display
+    Rect,
+    /// `left,top,right,bottom` around every display
+    Desktop,
+}
+
+impl std::str::FromStr for Win32Format {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(Win32Format::Size),
+            "rect" => Ok(Win32Format::Rect),
+            "desktop" => Ok(Win32Format::Desktop),
+            _ => Err(eyre::eyre!("invalid Win32 format ({}), expected size, rect or desktop", s)),
+        }
+    }
+}
+
+/// How to pick a mode from a connector's mode list
+#[derive(Clone, Copy)]
+enum ModeSelector {
+    /// Largest mode, by area
+    Max,
+    /// Mode closest to a size
+    Closest(u16, u16),
+}
+
+impl Report {
+    /// Whether the report already identifies the display, so it needs no `Display #N:` label
+    fn is_labeled(&self) -> bool {
+        matches!(
+            self,
+            Report::Kv
+                | Report::Capabilities
+                | Report::Modes
+                | Report::XrandrCmd
+                | Report::Cvt
+                | Report::Oneline
+        )
+    }
+}
+
+/// Refresh rate families
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RefreshClass {
+    /// Whole numbers, e.g. 60Hz
+    Integer,
+    /// NTSC style rates, e.g. 59.94Hz
+    Fractional,
+}
+
+impl RefreshClass {
+    /// Classify a refresh rate
+    ///
+    /// A rate is an integer when it's within 0.05% of a whole number. NTSC rates (N*1000/1001)
+    /// are 0.1% off, so 23.976 and 59.94 are fractional.
+    pub fn of(refresh: f64) -> Self {
+        if (refresh - refresh.round()).abs() < refresh * 0.0005 {
+            RefreshClass::Integer
+        } else {
+            RefreshClass::Fractional
+        }
+    }
+}
+
+impl std::str::FromStr for RefreshClass {
+    type Err = eyre::Report;
 
-impl std::os::unix::io::AsRawFd for Card {
-    fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
-        self.0.as_raw_fd()
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(RefreshClass::Integer),
+            "fractional" => Ok(RefreshClass::Fractional),
+            _ => Err(eyre::eyre!("invalid refresh filter ({}), expected integer or fractional", s)),
+        }
     }
 }
 
-impl Card {
-    pub fn open<P: AsRef<path::Path>>(path: P) -> Self {
-        let mut options = std::fs::OpenOptions::new();
-        options.read(true);
-        options.write(true);
-        Card(options.open(path).unwrap())
+/// Mode filtering settings, for the modes listed or picked from a connector's mode list
+#[derive(Default)]
+pub struct ModeFilter {
+    /// Only keep refresh rates of this family
+    pub refresh: Option<RefreshClass>,
+    /// Only keep modes at least this wide
+    pub min_width: Option<u16>,
+    /// Only keep modes with at least this refresh rate
+    pub min_refresh: Option<f64>,
+    /// Only keep modes of this aspect ratio (width / height), within `ASPECT_TOLERANCE`
+    pub aspect: Option<f64>,
+}
+
+impl ModeFilter {
+    /// Whether a mode passes the filter
+    pub fn matches(&self, mode: &Mode) -> bool {
+        if let Some(class) = self.refresh {
+            if RefreshClass::of(refresh_rate(mode)) != class {
+                return false;
+            }
+        }
+        if let Some(width) = self.min_width {
+            if mode.size().0 < width {
+                return false;
+            }
+        }
+        if let Some(hz) = self.min_refresh {
+            if refresh_rate(mode) < hz {
+                return false;
+            }
+        }
+        if let Some(aspect) = self.aspect {
+            let (x, y) = mode.size();
+            if (f64::from(x) / f64::from(y) - aspect).abs() > aspect * ASPECT_TOLERANCE {
+                return false;
+            }
+        }
+        true
     }
 }
 
-// Implement `drm` types
-impl Device for Card {}
-impl ControlDevice for Card {}
+/// Output settings
+#[derive(Default)]
+struct Output {
+    /// List every display instead of a single one
+    multi: bool,
+    /// Print a Wine virtual desktop size instead of per display reports
+    wine_best: bool,
+    /// Pick the display from a terminal menu
+    interactive: bool,
+    /// Pick the display covering this point of the desktop
+    at: Option<(u32, u32)>,
+    /// Pick the display with the highest DPI
+    highest_dpi: bool,
+    /// Print the `WxH` of the display picked by `simple_display`
+    simple: bool,
+    /// What to print for each display
+    report: Report,
+    /// Filter for the mode list
+    modes: ModeFilter,
+    /// Machine readable outputs to write, with their path (`-` for stdout)
+    sinks: Vec<(format::Format, String)>,
+    /// Include the raw EDID in machine readable outputs that support it
+    with_edid: bool,
+    /// Append the native resolution when it differs from the current one
+    with_native: bool,
+    /// Report the scanout framebuffer size instead of the mode size
+    scanout: bool,
+    /// Append the refresh rate and frame time to resolutions and modes
+    frametime: bool,
+    /// Refresh rate to prefer when several modes share the picked size
+    prefer_refresh: Option<f64>,
+    /// Maximum distance to the `--set-refresh` rate, instead of `DEFAULT_REFRESH_TOLERANCE`
+    refresh_tolerance: Option<f64>,
+    /// Write the regular output to this file instead of stdout
+    file: Option<String>,
+    /// In watch mode, only write the outputs when they change
+    only_changed: bool,
+    /// In watch mode, print how long the mode of each display has been set
+    mode_age: bool,
+}
 
 fn main() -> eyre::Result<()> {
     // Settings
     let mut verbosity = log::LevelFilter::Warn;
-    let mut multi = false;
+    let mut logger = true;
+    let mut list_cards = false;
+    let mut connector_stats = false;
+    let mut caps = false;
+    let mut laptop_docked = false;
+    let mut expect: Option<usize> = None;
+    let mut output = Output::default();
+    let mut scan = ScanOptions::default();
     let mut card: Option<String> = None;
+    let mut seat: Option<String> = None;
+    let mut timeout: Option<Duration> = None;
+    let mut watch: Option<Duration> = None;
+    let mut diff: Option<(String, String)> = None;
+    let mut wait_for: Option<ModeTarget> = None;
+    let mut wait_connector: Option<String> = None;
+    let mut wait_timeout = DEFAULT_WAIT_TIMEOUT;
+    let mut dbus = false;
 
     // Handle CLI
     {
@@ -81,11 +602,239 @@ fn main() -> eyre::Result<()> {
         while let Some(arg) = parser.next()? {
             match arg {
                 Short('m') | Long("multi") => {
-                    multi = true;
+                    output.multi = true;
+                }
+                Long("list-cards") => {
+                    list_cards = true;
+                }
+                Long("connector-stats") => {
+                    connector_stats = true;
+                }
+                Long("caps") => {
+                    caps = true;
+                }
+                Short('i') | Long("interactive") => {
+                    output.interactive = true;
+                }
+                Long("expect-single") => {
+                    expect = Some(1);
+                }
+                Long("expect") => {
+                    expect = Some(parser.value()?.parse()?);
+                }
+                Long("laptop-docked") => {
+                    laptop_docked = true;
+                }
+                Long("wine-best") => {
+                    output.wine_best = true;
+                }
+                Long("win32") => {
+                    output.report = Report::Win32(parser.value()?.parse()?);
+                }
+                Long("with-native") => {
+                    output.with_native = true;
+                }
+                Long("edid-max") => {
+                    output.report = Report::EdidMax;
+                }
+                Long("chromaticity") => {
+                    output.report = Report::Chromaticity;
+                }
+                Long("capability-report") => {
+                    output.report = Report::Capabilities;
+                }
+                Long("id") => {
+                    output.report = Report::Id;
+                }
+                Long("list-modes") => {
+                    output.report = Report::Modes;
+                }
+                Long("max") => {
+                    output.report = Report::Select(ModeSelector::Max);
+                }
+                Long("closest") => {
+                    let (x, y) = parse_size(&parser.value()?.into_string().unwrap())?;
+                    output.report = Report::Select(ModeSelector::Closest(x, y));
+                }
+                Long("prefer-refresh") => {
+                    output.prefer_refresh = Some(parser.value()?.parse()?);
+                }
+                Long("set-refresh") => {
+                    output.report = Report::SetRefresh(parser.value()?.parse()?);
+                }
+                Long("refresh-tolerance") => {
+                    output.refresh_tolerance = Some(parser.value()?.parse()?);
+                }
+                Long("refresh-filter") => {
+                    output.modes.refresh = Some(parser.value()?.parse()?);
+                }
+                Long("min-width") => {
+                    output.modes.min_width = Some(parser.value()?.parse()?);
+                }
+                Long("min-refresh") => {
+                    output.modes.min_refresh = Some(parser.value()?.parse()?);
+                }
+                Long("aspect-filter") => {
+                    let ratio = parser.value()?.into_string().unwrap();
+                    output.modes.aspect = Some(parse_aspect(&ratio)?);
+                }
+                Long("json") => {
+                    output.sinks.push((format::Format::Json, "-".to_string()));
+                }
+                Long("with-edid") => {
+                    output.with_edid = true;
+                }
+                Long("csv") => {
+                    output.sinks.push((format::Format::Csv, "-".to_string()));
+                }
+                Long("prometheus") => {
+                    output.sinks.push((format::Format::Prometheus, "-".to_string()));
+                }
+                Long("prometheus-file") => {
+                    let path = parser.value()?.into_string().unwrap();
+                    output.sinks.push((format::Format::Prometheus, path));
+                }
+                Long("json-file") => {
+                    let path = parser.value()?.into_string().unwrap();
+                    output.sinks.push((format::Format::Json, path));
+                }
+                Long("csv-file") => {
+                    let path = parser.value()?.into_string().unwrap();
+                    output.sinks.push((format::Format::Csv, path));
+                }
+                Long("xrandr-cmd") => {
+                    output.report = Report::XrandrCmd;
+                }
+                Long("tearing") => {
+                    output.report = Report::Tearing;
+                }
+                Long("cvt") => {
+                    output.report = Report::Cvt;
+                }
+                Long("oneline") => {
+                    output.report = Report::Oneline;
+                }
+                Long("kv") => {
+                    output.report = Report::Kv;
+                }
+                Long("input-type") => {
+                    output.report = Report::InputType;
+                }
+                Long("audio") => {
+                    output.report = Report::Audio;
+                }
+                Long("freesync-range") => {
+                    output.report = Report::FreesyncRange;
+                }
+                Long("fbdev") => {
+                    output.report = Report::Fbdev;
+                }
+                Long("edid-source") => {
+                    output.report = Report::EdidSource;
+                }
+                Long("hdr-active") => {
+                    output.report = Report::HdrActive;
+                }
+                Long("touch") => {
+                    output.report = Report::Touch;
+                }
+                Long("vblank") => {
+                    output.report = Report::Vblank;
+                    scan.vblank = true;
+                }
+                Long("fractional") => {
+                    output.report = Report::Fractional;
+                }
+                Long("scanout") => {
+                    output.scanout = true;
+                }
+                Long("frametime") => {
+                    output.frametime = true;
+                }
+                Long("exclude-connector") => {
+                    scan.exclude.push(parser.value()?.into_string().unwrap());
+                }
+                Long("min-modes") => {
+                    scan.min_modes = parser.value()?.parse()?;
+                }
+                Long("keep-phantom") => {
+                    scan.keep_phantom = true;
+                }
+                Long("normalize-names") => {
+                    scan.normalize_names = true;
+                }
+                Long("sort") => {
+                    scan.sort = Some(parser.value()?.parse()?);
+                }
+                Long("filter") => {
+                    scan.filter = Some(parser.value()?.parse()?);
+                }
+                Long("edid-override") => {
+                    let value = parser.value()?.into_string().unwrap();
+                    let (name, file) = value.split_once('=').ok_or_else(|| {
+                        eyre::eyre!("invalid EDID override ({}), expected CONNECTOR=FILE", value)
+                    })?;
+                    scan.edid_overrides.insert(name.to_string(), read_edid_file(file)?);
+                }
+                Long("seat") => {
+                    seat = Some(parser.value()?.into_string().unwrap());
                 }
                 Short('c') | Long("card") => {
                     card = Some(parser.value()?.into_string().unwrap());
                 }
+                Long("at") => {
+                    output.at = Some(parse_point(&parser.value()?.into_string().unwrap())?);
+                }
+                Long("highest-dpi") => {
+                    output.highest_dpi = true;
+                }
+                Long("simple") => {
+                    output.simple = true;
+                }
+                Long("diff") => {
+                    let files: Vec<String> = parser
+                        .values()?
+                        .take(2)
+                        .map(|v| v.into_string().unwrap())
+                        .collect();
+                    match files.as_slice() {
+                        [a, b] => diff = Some((a.clone(), b.clone())),
+                        _ => return Err(eyre::eyre!("--diff needs two snapshots")),
+                    }
+                }
+                Short('w') | Long("watch") => {
+                    let secs: u64 = parser.value()?.parse()?;
+                    if secs == 0 {
+                        return Err(eyre::eyre!("--watch needs an interval of at least 1s"));
+                    }
+                    watch = Some(Duration::from_secs(secs));
+                }
+                Short('o') | Long("output-file") => {
+                    output.file = Some(parser.value()?.into_string().unwrap());
+                }
+                Long("only-changed") => {
+                    output.only_changed = true;
+                }
+                Long("mode-age") => {
+                    output.mode_age = true;
+                }
+                Long("wait-for-mode") => {
+                    wait_for = Some(parse_mode_target(&parser.value()?.into_string().unwrap())?);
+                }
+                Long("wait-connector") => {
+                    wait_connector = Some(parser.value()?.into_string().unwrap());
+                }
+                Long("wait-timeout") => {
+                    let secs: u64 = parser.value()?.parse()?;
+                    wait_timeout = Duration::from_secs(secs);
+                }
+                Long("dbus") => {
+                    dbus = true;
+                }
+                Short('t') | Long("timeout") => {
+                    let secs: u64 = parser.value()?.parse()?;
+                    timeout = Some(Duration::from_secs(secs));
+                }
                 Short('h') | Long("help") => {
                     println!("{}", USAGE);
                     process::exit(0);
@@ -96,6 +845,9 @@ fn main() -> eyre::Result<()> {
                 Short('q') | Long("quiet") => {
                     verbosity = decrement_loglevel(verbosity);
                 }
+                Long("no-logger") => {
+                    logger = false;
+                }
                 _ => return Err(eyre::eyre!("{}", arg.unexpected())),
             }
         }
@@ -111,128 +863,1135 @@ fn main() -> eyre::Result<()> {
         }
     }
 
-    // Init logger
-    SimpleLogger::new().with_level(verbosity).init()?;
+    // Init logger. Without one, the log macros do nothing
+    if logger {
+        SimpleLogger::new().with_level(verbosity).init()?;
+    }
+
+    if let Some((a, b)) = diff {
+        let changes = format::diff(&read_snapshot(&a)?, &read_snapshot(&b)?);
+        if changes.is_empty() {
+            println!("no changes");
+        }
+        for line in changes {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    // Default to our own seat, unless a card was picked by hand
+    if card.is_none() {
+        seat = seat.or_else(|| env::var("XDG_SEAT").ok());
+    }
 
-    // Store found displays
-    let mut displays: Vec<Mode> = vec![];
     // Store the checked cards
-    let mut cards: Vec<path::PathBuf> = vec![];
+    let sandboxed = in_flatpak();
+    let cards = gpu::find_cards(card.as_deref());
+    let mut cards = if sandboxed { cards.wrap_err(FLATPAK_HINT)? } else { cards? };
+
+    // Only keep the cards of the selected seat
+    if let Some(seat) = seat {
+        cards.retain(|file| {
+            let card_seat = sysfs::seat(file);
+            if card_seat != seat {
+                log::info!("Skipping {} (assigned to {})", file.display(), card_seat);
+            }
+            card_seat == seat
+        });
+    }
 
-    if let Some(c) = card {
-        // Open single card
-        let mut file = path::PathBuf::from("/dev/dri/");
-        file.push(&c);
-        if !file.exists() || !c.starts_with("card") {
-            return Err(eyre::eyre!("invalid card ({})", c));
+    // Sandboxes may only expose some of the nodes, skip the ones we can't open
+    cards.retain(|file| match gpu::probe(file) {
+        Ok(_) => true,
+        Err(e) if sandboxed => {
+            log::debug!("Skipping {:#}", e);
+            false
         }
-        cards.push(file);
-    } else {
-        // Open every card on the system
-        for entry in fs::read_dir("/dev/dri/")? {
-            let file = entry?;
+        Err(e) => {
+            log::warn!("Skipping {:#}", e);
+            false
+        }
+    });
+    if cards.is_empty() && sandboxed {
+        return Err(eyre::eyre!("no GPU can be opened, {}", FLATPAK_HINT));
+    }
+
+    if list_cards {
+        for (file, info) in cards.iter().zip(read_card_infos(&cards, timeout)?) {
+            println!(
+                "{}: {} ({} connected)",
+                file.file_name().unwrap_or_default().to_string_lossy(),
+                info.driver,
+                info.connected
+            );
+        }
+        return Ok(());
+    }
+
+    if connector_stats {
+        for (file, info) in cards.iter().zip(read_card_infos(&cards, timeout)?) {
+            println!(
+                "{}: {}/{}",
+                file.file_name().unwrap_or_default().to_string_lossy(),
+                info.connected,
+                info.total
+            );
+        }
+        return Ok(());
+    }
 
-            if let Some(name) = file.file_name().to_str() {
-                if name.starts_with("card") {
-                    cards.push(file.path());
+    if caps {
+        let files = cards.clone();
+        let found = with_timeout(timeout, move || {
+            let caps = files.iter().map(|f| Ok((gpu::card_info(f)?, gpu::driver_caps(f)?)));
+            caps.collect::<eyre::Result<Vec<_>>>()
+        })?;
+        for (file, (info, caps)) in cards.iter().zip(found) {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            println!("{} ({}):", name, info.driver);
+            // rres never calls DRM_IOCTL_SET_CLIENT_CAP
+            println!("  client caps: none (legacy modesetting API)");
+            for (name, value) in caps {
+                match value {
+                    Some(value) => println!("  {}: {}", name, value),
+                    None => println!("  {}: unknown", name),
                 }
             }
         }
+        return Ok(());
     }
 
-    // Sort cards (card0, card1, card2...)
-    cards.sort();
+    // Names given on the command line are matched against the normalized ones
+    if scan.normalize_names {
+        scan.exclude = scan.exclude.iter().map(|n| names::normalize(n)).collect();
+        scan.edid_overrides =
+            scan.edid_overrides.drain().map(|(n, edid)| (names::normalize(&n), edid)).collect();
+        if let Some(filter) = &mut scan.filter {
+            filter.normalize_names();
+        }
+        wait_connector = wait_connector.map(|n| names::normalize(&n));
+    }
+
+    if watch.is_some() && (output.interactive || laptop_docked) {
+        return Err(eyre::eyre!("--interactive and --laptop-docked can't be used while watching"));
+    }
+    if watch.is_none() && output.mode_age {
+        return Err(eyre::eyre!("--mode-age requires --watch"));
+    }
+
+    let scan = Arc::new(scan);
+    if dbus {
+        #[cfg(feature = "dbus")]
+        return dbus::serve(cards, scan, timeout, watch);
+        #[cfg(not(feature = "dbus"))]
+        return Err(eyre::eyre!("--dbus requires building rres with the dbus feature"));
+    }
+    if let Some(mut target) = wait_for {
+        target.connector = wait_connector;
+        let tolerance = output.refresh_tolerance.unwrap_or(DEFAULT_REFRESH_TOLERANCE);
+        if !wait_for_mode(&cards, &scan, timeout, &target, tolerance, wait_timeout)? {
+            log::error!("timed out waiting for the mode");
+            process::exit(EXIT_WAIT_TIMEOUT);
+        }
+        return Ok(());
+    }
+    if let Some(interval) = watch {
+        return watch_displays(&cards, &scan, timeout, &output, interval);
+    }
 
     // Read card list
-    for file in cards {
-        let gpu = Card::open(file);
-        let info = gpu.get_driver()?;
-        log::info!("Found GPU: {}", info.name().to_string_lossy());
-        // Find displays
-        match get_card_modes(gpu) {
-            Ok(modes) => displays.extend_from_slice(&modes),
-            Err(e) => log::error!("failed to read modes: {}", e),
+    let displays = scan_displays(&cards, &scan, timeout)?;
+
+    if let Some(expected) = expect {
+        if displays.len() != expected {
+            log::error!("expected {} displays, found {}", expected, displays.len());
+            process::exit(EXIT_UNEXPECTED_COUNT);
+        }
+        // Nothing to print, and no display was the expected outcome
+        if expected == 0 {
+            return Ok(());
         }
     }
 
+    // No display at all means no internal panel
+    if laptop_docked {
+        process::exit(check_docked(&displays, &output)?);
+    }
+
     if displays.is_empty() {
         log::error!("found no display connected!");
         process::exit(1);
     }
 
-    if multi {
+    let text = render(&displays, &output)?;
+    write_outputs(&displays, &text, &output)
+}
+
+/// Check whether a laptop is docked, printing the first external display when it is
+///
+/// Returns the exit code: 0 when docked (the internal panel and an external display are
+/// connected), 1 when only the internal panel is connected and 2 when there's no internal panel.
+fn check_docked(displays: &[Display], output: &Output) -> eyre::Result<i32> {
+    if !displays.iter().any(|d| d.internal) {
+        log::warn!("no internal panel found");
+        return Ok(2);
+    }
+    match displays.iter().position(|d| !d.internal) {
+        Some(external) => {
+            println!("{}", format_display(external, &displays[external], output)?);
+            Ok(0)
+        }
+        None => Ok(1),
+    }
+}
+
+/// Whether rres runs inside a Flatpak sandbox
+fn in_flatpak() -> bool {
+    path::Path::new("/.flatpak-info").exists()
+}
+
+/// Read the displays connected to every card in `cards`, giving up after `timeout`
+fn scan_displays(
+    cards: &[path::PathBuf],
+    scan: &Arc<ScanOptions>,
+    timeout: Option<Duration>,
+) -> eyre::Result<Vec<Display>> {
+    let cards = cards.to_vec();
+    let scan = Arc::clone(scan);
+    with_timeout(timeout, move || enumerate_displays(cards, &scan))
+}
+
+/// Read the driver and connector counts of every card in `cards`, giving up after `timeout`
+fn read_card_infos(
+    cards: &[path::PathBuf],
+    timeout: Option<Duration>,
+) -> eyre::Result<Vec<CardInfo>> {
+    let cards = cards.to_vec();
+    with_timeout(timeout, move || cards.iter().map(|f| gpu::card_info(f)).collect())
+}
+
+/// Run GPU reads, giving up after `timeout`
+fn with_timeout<T, F>(timeout: Option<Duration>, work: F) -> eyre::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> eyre::Result<T> + Send + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return work(),
+    };
+    // Some drivers can hang in an ioctl, so read on a separate thread.
+    // On timeout, the worker is left behind and whatever it found is discarded.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(work());
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            Err(eyre::eyre!("timed out after {}s", timeout.as_secs()))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(eyre::eyre!("GPU read failed")),
+    }
+}
+
+/// Read the displays every `interval`, writing the outputs after each scan
+///
+/// With `--only-changed`, the outputs are only written when the regular output or any display's
+/// record (what the machine readable outputs hold) differs from the last written ones, so output
+/// files keep their mtime while nothing changes.
+///
+/// With `--mode-age`, the time since the mode of each display last changed goes to stderr, so it
+/// doesn't count as a change. Displays seen for the first time start at 0.
+///
+/// A scan that fails or times out, or an output that can't be rendered (no display at `--at`,
+/// say), is logged and the tick skipped, as the next scan may well succeed.
+fn watch_displays(
+    cards: &[path::PathBuf],
+    scan: &Arc<ScanOptions>,
+    timeout: Option<Duration>,
+    output: &Output,
+    interval: Duration,
+) -> eyre::Result<()> {
+    let mut last: Option<(String, String)> = None;
+    // Mode of every display by card and connector, as two cards can both have a DP-1, and when
+    // it was first seen
+    let mut since: HashMap<(String, String), (String, Instant)> = HashMap::new();
+    loop {
+        let displays = match scan_displays(cards, scan, timeout) {
+            Ok(displays) => displays,
+            Err(e) => {
+                log::error!("{:#}", e);
+                thread::sleep(interval);
+                continue;
+            }
+        };
+        if output.mode_age {
+            let now = Instant::now();
+            since.retain(|(card, connector), _| {
+                displays.iter().any(|d| &d.card == card && &d.connector == connector)
+            });
+            for display in &displays {
+                let mode = format_mode(&display.mode, output);
+                let key = (display.card.clone(), display.connector.clone());
+                let entry = since.entry(key).or_insert((mode.clone(), now));
+                if entry.0 != mode {
+                    *entry = (mode, now);
+                }
+                let age = now.duration_since(entry.1).as_secs();
+                eprintln!("{} {}: {} for {}s", display.card, display.connector, entry.0, age);
+            }
+        }
+        let text = if displays.is_empty() {
+            log::warn!("found no display connected!");
+            String::new()
+        } else {
+            match render(&displays, output) {
+                Ok(text) => text,
+                Err(e) => {
+                    log::error!("{:#}", e);
+                    thread::sleep(interval);
+                    continue;
+                }
+            }
+        };
+        let records: Vec<format::Record> =
+            displays.iter().enumerate().map(|(i, d)| record(i, d, output)).collect();
+        let snapshot = (text, serde_json::to_string(&records)?);
+        if !output.only_changed || last.as_ref() != Some(&snapshot) {
+            write_outputs(&displays, &snapshot.0, output)?;
+            last = Some(snapshot);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Read the displays until one of them is set to `target`, for up to `wait`
+///
+/// Returns whether the mode showed up in time.
+fn wait_for_mode(
+    cards: &[path::PathBuf],
+    scan: &Arc<ScanOptions>,
+    timeout: Option<Duration>,
+    target: &ModeTarget,
+    tolerance: f64,
+    wait: Duration,
+) -> eyre::Result<bool> {
+    let start = Instant::now();
+    loop {
+        let displays = scan_displays(cards, scan, timeout)?;
+        let found = displays.iter().any(|d| {
+            target.connector.as_ref().is_none_or(|c| &d.connector == c)
+                && d.mode.size() == target.size
+                && target.refresh.is_none_or(|hz| (refresh_rate(&d.mode) - hz).abs() <= tolerance)
+        });
+        if found {
+            return Ok(true);
+        }
+        if start.elapsed() >= wait {
+            return Ok(false);
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Render the regular output
+fn render(displays: &[Display], output: &Output) -> eyre::Result<String> {
+    let mut lines: Vec<String> = vec![];
+    if output.simple {
+        let (x, y) = displays[simple_display(displays)].mode.size();
+        lines.push(format!("{}x{}", x, y));
+    } else if output.wine_best {
+        let (x, y) = wine_best_resolution(displays);
+        lines.push(format!("{}x{}", x, y));
+    } else if let Report::Win32(Win32Format::Desktop) = output.report {
+        let (left, top, right, bottom) = desktop_rect(displays);
+        lines.push(format!("{},{},{},{}", left, top, right, bottom));
+    } else if output.interactive {
+        let selection = select_display(displays)?;
+        lines.push(format_display(selection, &displays[selection], output)?);
+    } else if let Some((x, y)) = output.at {
+        let selection = display_at(displays, x, y)
+            .ok_or_else(|| eyre::eyre!("no display covers {},{}", x, y))?;
+        lines.push(format_display(selection, &displays[selection], output)?);
+    } else if output.highest_dpi {
+        let selection = highest_dpi_display(displays).unwrap_or_else(|| {
+            log::warn!("no display has a known physical size, using the first one");
+            0
+        });
+        lines.push(format_display(selection, &displays[selection], output)?);
+    } else if output.multi {
         // List every display
-        for (i, mode) in displays.iter().enumerate() {
-            let res = mode.size();
-            println!("Display #{}: {}x{}", i, res.0, res.1);
+        for (i, display) in displays.iter().enumerate() {
+            if output.report.is_labeled() {
+                lines.push(format_display(i, display, output)?);
+            } else {
+                lines.push(format!("Display #{}: {}", i, format_display(i, display, output)?));
+            }
         }
     } else {
         let selection: usize = env::var("RRES_DISPLAY")
             .unwrap_or_else(|_| "0".to_string())
             .parse()
             .wrap_err("Failed to parse RRES_DISPLAY")?;
-        if selection > displays.len() - 1 {
-            return Err(eyre::eyre!("invalid display: {}", selection));
-        }
+        check_selection(selection, displays.len())?;
         // Print res of first display
-        let res = displays[selection].size();
-        println!("{}x{}", res.0, res.1);
+        lines.push(format_display(selection, &displays[selection], output)?);
     }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    Ok(text)
+}
 
+/// Check that a display index is in range of `count` displays
+fn check_selection(selection: usize, count: usize) -> eyre::Result<()> {
+    if selection >= count {
+        return Err(eyre::eyre!("invalid display: {}", selection));
+    }
     Ok(())
 }
 
-/// Get all the connected display's modes from a libdrm card.
-pub fn get_card_modes<G: ControlDevice>(gpu: G) -> eyre::Result<Vec<Mode>> {
-    let mut modes: Vec<Mode> = vec![];
+/// Write the machine readable outputs, then the regular output
+fn write_outputs(displays: &[Display], text: &str, output: &Output) -> eyre::Result<()> {
+    for (format, path) in &output.sinks {
+        let records = displays.iter().enumerate().map(|(i, d)| record(i, d, output)).collect();
+        if path == "-" {
+            format::write(*format, records, &mut std::io::stdout().lock())?;
+        } else {
+            write_file(path, |file| format::write(*format, records, file))?;
+        }
+    }
+    match &output.file {
+        Some(path) => write_file(path, |file| Ok(file.write_all(text.as_bytes())?))?,
+        // Don't mix the default output with a machine readable one
+        None if output.sinks.iter().any(|(_, path)| path == "-") => (),
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Replace the file at `path` with what `write` puts in it
+///
+/// It's written next to it first and then renamed over it, so whoever reads it while watching
+/// never sees it half written.
+fn write_file<F>(path: &str, write: F) -> eyre::Result<()>
+where
+    F: FnOnce(&mut fs::File) -> eyre::Result<()>,
+{
+    let target = path::Path::new(path);
+    let name = target.file_name().ok_or_else(|| eyre::eyre!("{} is not a file", path))?;
+    let temp = target.with_file_name(format!(".{}.rres-tmp", name.to_string_lossy()));
+    let result = fs::File::create(&temp)
+        .wrap_err_with(|| format!("failed to create {}", temp.display()))
+        .and_then(|mut file| write(&mut file))
+        .and_then(|()| {
+            fs::rename(&temp, target).wrap_err_with(|| format!("failed to write {}", path))
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Read the displays connected to every card in `cards`
+pub fn enumerate_displays(
+    cards: Vec<path::PathBuf>,
+    scan: &ScanOptions,
+) -> eyre::Result<Vec<Display>> {
+    // Store found displays
+    let mut found = vec![];
+    for file in cards {
+        found.push(gpu::read_card(&file, scan)?);
+    }
+    // Overrides are looked up by name, so a typo would silently do nothing
+    for name in scan.edid_overrides.keys() {
+        let displays = || found.iter().flatten();
+        if displays().any(|d| &d.connector == name) {
+            continue;
+        }
+        match displays().find(|d| names::normalize(&d.connector) == names::normalize(name)) {
+            Some(d) => {
+                log::warn!("EDID override for {} unused, did you mean {}?", name, d.connector)
+            }
+            None => log::warn!("EDID override for {} unused, no such connected display", name),
+        }
+    }
+    Ok(aggregate_displays(found, scan))
+}
+
+/// Merge the displays of every card, in card order, then filter and sort them
+///
+/// Displays are never merged across cards: with hybrid graphics, a display wired to both GPUs is
+/// listed once per card.
+fn aggregate_displays(cards: Vec<Vec<Display>>, scan: &ScanOptions) -> Vec<Display> {
+    let mut displays: Vec<Display> = cards.into_iter().flatten().collect();
+    if let Some(filter) = &scan.filter {
+        displays.retain(|d| filter.matches(d));
+    }
+    match scan.sort {
+        Some(SortKey::Name) => displays.sort_by(|a, b| a.connector.cmp(&b.connector)),
+        Some(SortKey::NameNatural) => {
+            displays.sort_by(|a, b| natural_cmp(&a.connector, &b.connector))
+        }
+        None => (),
+    }
+    displays
+}
 
-    let resources = gpu.resource_handles().wrap_err("failed to get resource handles")?;
-    let connectors = resources.connectors();
-    for handle in connectors {
-        let connector = gpu.get_connector(*handle).wrap_err("failed to get connector handle")?;
-        if connector.state() == drm::control::connector::State::Connected {
-            // Connected, get mode
-            modes.push(get_connector_mode(&gpu, connector)?);
+/// Compare strings with their numbers compared by value, so DP-9 comes before DP-10
+///
+/// Numbers that only differ in leading zeros fall back to comparing the strings as they are,
+/// so only equal strings are equal.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut left, mut right) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (left.peek(), right.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut number = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                        number.push(c);
+                    }
+                    number.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (take_number(&mut left), take_number(&mut right));
+                // Without leading zeros, a longer number is a bigger one
+                let order = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if order != std::cmp::Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.cmp(y);
+                if order != std::cmp::Ordering::Equal {
+                    return order;
+                }
+                left.next();
+                right.next();
+            }
         }
     }
-    Ok(modes)
 }
 
-/// Get current display mode from connector
+/// Parse a `WIDTHxHEIGHT` size
+fn parse_size(size: &str) -> eyre::Result<(u16, u16)> {
+    let (x, y) = size
+        .split_once('x')
+        .ok_or_else(|| eyre::eyre!("invalid size ({}), expected WIDTHxHEIGHT", size))?;
+    let x = x.trim().parse().wrap_err("invalid width")?;
+    let y = y.trim().parse().wrap_err("invalid height")?;
+    Ok((x, y))
+}
+
+/// Parse a `WIDTHxHEIGHT[@REFRESH]` mode
+fn parse_mode_target(mode: &str) -> eyre::Result<ModeTarget> {
+    let (size, refresh) = match mode.split_once('@') {
+        Some((size, refresh)) => (size, Some(refresh.trim().parse().wrap_err("invalid refresh")?)),
+        None => (mode, None),
+    };
+    Ok(ModeTarget {
+        size: parse_size(size)?,
+        refresh,
+        connector: None,
+    })
+}
+
+/// Parse a `W:H` aspect ratio, e.g. 16:9
+fn parse_aspect(ratio: &str) -> eyre::Result<f64> {
+    let invalid = || eyre::eyre!("invalid aspect ratio ({}), expected W:H, e.g. 16:9", ratio);
+    let (w, h) = ratio.split_once(':').ok_or_else(invalid)?;
+    let w: f64 = w.trim().parse().map_err(|_| invalid())?;
+    let h: f64 = h.trim().parse().map_err(|_| invalid())?;
+    if !(w > 0.0 && h > 0.0 && w.is_finite() && h.is_finite()) {
+        return Err(invalid());
+    }
+    Ok(w / h)
+}
+
+/// Parse a `X,Y` coordinate
+fn parse_point(point: &str) -> eyre::Result<(u32, u32)> {
+    let (x, y) = point
+        .split_once(',')
+        .ok_or_else(|| eyre::eyre!("invalid coordinate ({}), expected X,Y", point))?;
+    let x = x.trim().parse().wrap_err("invalid X coordinate")?;
+    let y = y.trim().parse().wrap_err("invalid Y coordinate")?;
+    Ok((x, y))
+}
+
+/// Find the display whose crtc covers a point of the global desktop
+///
+/// Displays without a crtc have no known position and are never matched.
+fn display_at(displays: &[Display], x: u32, y: u32) -> Option<usize> {
+    displays.iter().position(|d| match d.position {
+        Some((cx, cy)) => {
+            let (w, h) = d.mode.size();
+            (cx..cx + u32::from(w)).contains(&x) && (cy..cy + u32::from(h)).contains(&y)
+        }
+        None => false,
+    })
+}
+
+/// Find the display with the highest DPI
 ///
-/// Note: nVidia GPUs don't share the current encoder+crtc, so this function will report the
-/// native display's resolution instead of the current resolution.
-fn get_connector_mode<G: ControlDevice>(
-    gpu: &G,
-    connector: drm::control::connector::Info,
-) -> eyre::Result<Mode> {
-    if connector.state() != drm::control::connector::State::Connected {
-        return Err(eyre::eyre!("Connector is disconnected"));
-    }
-    if let Some(encoder_handle) = connector.current_encoder() {
-        // Get the encoder then crtc
-        let encoder = gpu.get_encoder(encoder_handle)?;
-        if let Some(crtc_handle) = encoder.crtc() {
-            let crtc = gpu.get_crtc(crtc_handle).wrap_err("failed to get crtc")?;
-            // Get current mode, and store it
-            if let Some(current_mode) = crtc.mode() {
-                log::info!(
-                    "Found display: {:?}, {}x{}",
-                    connector.interface(),
-                    current_mode.size().0,
-                    current_mode.size().1
-                );
-                return Ok(current_mode);
+/// Displays without a known physical size are skipped.
+fn highest_dpi_display(displays: &[Display]) -> Option<usize> {
+    displays
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| match dpi(d) {
+            Some(dpi) => Some((i, dpi)),
+            None => {
+                log::info!("Skipping {}: unknown physical size", d.connector);
+                None
             }
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+}
+
+/// Pick the display most users mean, for `--simple`
+///
+/// Displays are ranked by, in order: being lit (driven by a CRTC), being primary (at 0,0 on the
+/// desktop), being external and the area of the current mode. Ties go to the lowest card, then
+/// connector name, so the scan order doesn't matter. `displays` must not be empty.
+fn simple_display(displays: &[Display]) -> usize {
+    let rank = |d: &Display| {
+        let (x, y) = d.mode.size();
+        let area = u32::from(x) * u32::from(y);
+        (d.position.is_some(), d.position == Some((0, 0)), !d.internal, area)
+    };
+    (0..displays.len())
+        .max_by(|&a, &b| {
+            let (a, b) = (&displays[a], &displays[b]);
+            rank(a)
+                .cmp(&rank(b))
+                .then_with(|| natural_cmp(&b.card, &a.card))
+                .then_with(|| natural_cmp(&b.connector, &a.connector))
+        })
+        .unwrap_or_default()
+}
+
+/// Compute the diagonal DPI of a display's current mode, from the EDID physical size
+fn dpi(display: &Display) -> Option<f64> {
+    let (mx, my) = display.edid.as_ref()?.physical_size()?;
+    let (x, y) = display.mode.size();
+    let pixels = f64::from(x).hypot(f64::from(y));
+    let inches = f64::from(mx).hypot(f64::from(my)) / 25.4;
+    Some(pixels / inches)
+}
+
+/// Let the user pick a display from a terminal menu
+fn select_display(displays: &[Display]) -> eyre::Result<usize> {
+    if !std::io::stdout().is_terminal() {
+        return Err(eyre::eyre!("interactive mode requires a terminal"));
+    }
+    let items: Vec<String> = displays
+        .iter()
+        .enumerate()
+        .map(|(i, d)| format!("#{} {}: {}x{}", i, d.connector, d.mode.size().0, d.mode.size().1))
+        .collect();
+    dialoguer::Select::new()
+        .with_prompt("Select a display")
+        .items(&items)
+        .default(0)
+        .interact_opt()?
+        .ok_or_else(|| eyre::eyre!("no display selected"))
+}
+
+/// Build the machine readable record of a display
+fn record(index: usize, display: &Display, output: &Output) -> format::Record {
+    let (width, height) = display.mode.size();
+    let edid = display.edid.as_ref().filter(|_| output.with_edid);
+    format::Record {
+        index,
+        card: display.card.clone(),
+        connector: display.connector.clone(),
+        width: width.into(),
+        height: height.into(),
+        refresh: refresh_rate(&display.mode),
+        edid_base64: edid.map(|e| base64::engine::general_purpose::STANDARD.encode(e.bytes())),
+    }
+}
+
+/// Format the requested report for a display, whose index is `index`.
+fn format_display(index: usize, display: &Display, output: &Output) -> eyre::Result<String> {
+    Ok(match output.report {
+        Report::Resolution => format_resolution(display, output),
+        Report::EdidMax => format_edid_max(display),
+        Report::Chromaticity => format_chromaticity(display),
+        Report::Kv => format_kv(display),
+        Report::Capabilities => format_capabilities(display),
+        Report::Id => format_id(display),
+        Report::Modes => format_modes(display, output),
+        Report::XrandrCmd => format_xrandr_cmd(display),
+        Report::Select(selector) => match select_mode(display, selector, output) {
+            Some(mode) => format_mode(&mode, output),
+            None => "none".to_string(),
+        },
+        Report::Tearing => match display.tearing {
+            Some(true) => "allowed".to_string(),
+            Some(false) => "not allowed".to_string(),
+            None => "unknown".to_string(),
+        },
+        Report::InputType => format_input_type(display),
+        Report::Audio => format_audio(display),
+        Report::Cvt => format_cvt(&display.mode),
+        Report::SetRefresh(hz) => format_mode(&refresh_mode(display, hz, output)?, output),
+        Report::Touch => format_touch(display).to_string(),
+        Report::Oneline => format_oneline(index, display, output),
+        Report::FreesyncRange => match display.edid.as_ref().and_then(|e| e.vrr_range()) {
+            Some((min, max)) => format!("{}-{}Hz", min, max),
+            None => "unknown".to_string(),
+        },
+        Report::Fbdev => match sysfs::fbdev(&display.card) {
+            Some(fb) => format!("/dev/{}", fb),
+            None => "none".to_string(),
+        },
+        Report::HdrActive => format_hdr(display),
+        Report::EdidSource => match display.edid_source {
+            edid::Source::Live => "live".to_string(),
+            edid::Source::Overridden => "overridden".to_string(),
+            edid::Source::Unknown => "unknown".to_string(),
+        },
+        Report::Win32(Win32Format::Size) => {
+            let (x, y) = display.mode.size();
+            format!("{},{}", x, y)
+        }
+        Report::Win32(_) => {
+            let (left, top, right, bottom) = display_rect(display);
+            format!("{},{},{},{}", left, top, right, bottom)
+        }
+        Report::Vblank => match &display.vblank {
+            Some(v) => format!("{} at {:.6}s", v.sequence, v.timestamp.as_secs_f64()),
+            None => "unavailable".to_string(),
+        },
+        Report::Fractional => format_fractional(display),
+    })
+}
+
+/// Format the HDR output state of a display, e.g. `active (PQ, MaxCLL 1000, MaxFALL 400)`.
+fn format_hdr(display: &Display) -> String {
+    let (eotf, max_cll, max_fall) = match display.hdr {
+        Some(HdrOutput::Active {
+            eotf,
+            max_cll,
+            max_fall,
+        }) => (eotf, max_cll, max_fall),
+        Some(HdrOutput::Inactive) => return "inactive".to_string(),
+        None => return "unknown".to_string(),
+    };
+    let mut details = vec![match eotf {
+        1 => "HDR gamma".to_string(),
+        2 => "PQ".to_string(),
+        3 => "HLG".to_string(),
+        n => format!("EOTF {}", n),
+    }];
+    if max_cll != 0 {
+        details.push(format!("MaxCLL {}", max_cll));
+    }
+    if max_fall != 0 {
+        details.push(format!("MaxFALL {}", max_fall));
+    }
+    format!("active ({})", details.join(", "))
+}
+
+/// Format a display on a line, e.g. `#0 HDMI-A-1 1920x1080@60 "DELL U2720Q"`.
+///
+/// The monitor name comes from the EDID, and is left out when there's none.
+fn format_oneline(index: usize, display: &Display, output: &Output) -> String {
+    let line = format!("#{} {} {}", index, display.connector, format_mode(&display.mode, output));
+    match display.edid.as_ref().and_then(|e| e.monitor_name()) {
+        Some(name) => format!("{} \"{}\"", line, name),
+        None => line,
+    }
+}
+
+/// Report whether a display is a touchscreen: `touch`, `no touch` or `unknown`.
+///
+/// Touchscreens tagged with an output name (`WL_OUTPUT`, in any naming scheme) belong to that
+/// display. Like compositors do, untagged ones are assumed to be on the built-in panel, so an
+/// external display with untagged touchscreens around is unknown.
+fn format_touch(display: &Display) -> &'static str {
+    let touchscreens = match sysfs::touchscreens() {
+        Some(touchscreens) => touchscreens,
+        None => return "unknown",
+    };
+    let tagged = touchscreens.iter().flatten().any(|t| {
+        *t == display.connector || names::normalize(t) == names::normalize(&display.connector)
+    });
+    let untagged = touchscreens.contains(&None);
+    if tagged || (display.internal && untagged) {
+        "touch"
+    } else if display.internal || !untagged {
+        "no touch"
+    } else {
+        "unknown"
+    }
+}
+
+/// Find the mode closest to a refresh rate among the filtered modes of the current resolution
+///
+/// Fails when no mode is within `--refresh-tolerance` of the rate.
+fn refresh_mode(display: &Display, hz: f64, output: &Output) -> eyre::Result<Mode> {
+    let (x, y) = display.mode.size();
+    let tolerance = output.refresh_tolerance.unwrap_or(DEFAULT_REFRESH_TOLERANCE);
+    display
+        .modes
+        .iter()
+        .filter(|m| m.size() == (x, y) && output.modes.matches(m))
+        .min_by(|a, b| (refresh_rate(a) - hz).abs().total_cmp(&(refresh_rate(b) - hz).abs()))
+        .filter(|m| (refresh_rate(m) - hz).abs() <= tolerance)
+        .copied()
+        .ok_or_else(|| eyre::eyre!("{} has no {}Hz mode at {}x{}", display.connector, hz, x, y))
+}
+
+/// Format a cvt command generating a mode.
+///
+/// CVT modes have a positive hsync and negative vsync with reduced blanking, and the opposite
+/// without it, so the sync polarities pick `cvt -r`.
+fn format_cvt(mode: &Mode) -> String {
+    let flags = mode.flags;
+    let mut cmd = "cvt".to_string();
+    if flags & mode::FLAG_PHSYNC != 0 && flags & mode::FLAG_NVSYNC != 0 {
+        cmd.push_str(" -r");
+    }
+    if flags & mode::FLAG_INTERLACE != 0 {
+        cmd.push_str(" -i");
+    }
+    let (x, y) = mode.size();
+    format!("{} {} {} {}", cmd, x, y, format_refresh(refresh_rate(mode)))
+}
+
+/// Format the audio formats from a display's EDID, e.g. `PCM 2ch, AC-3 6ch`.
+fn format_audio(display: &Display) -> String {
+    let formats = match &display.edid {
+        Some(edid) => edid.audio_formats(),
+        None => return "unknown".to_string(),
+    };
+    if formats.is_empty() {
+        return "none".to_string();
+    }
+    formats
+        .iter()
+        .map(|a| format!("{} {}ch", a.format, a.channels))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Format the video input definition from a display's EDID, e.g. `digital (HDMI-a, 8 bit)`.
+fn format_input_type(display: &Display) -> String {
+    match display.edid.as_ref().map(|e| e.video_input()) {
+        Some(edid::VideoInput::Digital {
+            interface,
+            bit_depth,
+        }) => {
+            let depth = match bit_depth {
+                Some(depth) => format!("{} bit", depth),
+                None => "unknown depth".to_string(),
+            };
+            format!("digital ({}, {})", interface.unwrap_or("unknown interface"), depth)
+        }
+        Some(edid::VideoInput::Analog) => "analog".to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Pick a mode from a display's filtered mode list
+///
+/// The selector picks a size. Among the modes of that size, the refresh rate closest to
+/// `--prefer-refresh` wins, or the highest one without it.
+fn select_mode(display: &Display, selector: ModeSelector, output: &Output) -> Option<Mode> {
+    let candidates: Vec<&Mode> = display.modes.iter().filter(|m| output.modes.matches(m)).collect();
+    let area = |(x, y): (u16, u16)| u32::from(x) * u32::from(y);
+    let size = match selector {
+        ModeSelector::Max => candidates.iter().map(|m| m.size()).max_by_key(|s| area(*s))?,
+        ModeSelector::Closest(x, y) => candidates
+            .iter()
+            .map(|m| m.size())
+            .min_by_key(|(mx, my)| {
+                let dx = i64::from(*mx) - i64::from(x);
+                let dy = i64::from(*my) - i64::from(y);
+                dx * dx + dy * dy
+            })?,
+    };
+    let same_size = candidates.into_iter().filter(|m| m.size() == size);
+    match output.prefer_refresh {
+        Some(hz) => same_size.min_by(|a, b| {
+            let da = (refresh_rate(a) - hz).abs();
+            let db = (refresh_rate(b) - hz).abs();
+            da.total_cmp(&db)
+        }),
+        None => same_size.max_by(|a, b| refresh_rate(a).total_cmp(&refresh_rate(b))),
+    }
+    .copied()
+}
+
+/// Format an xrandr command that sets a display to its current mode.
+///
+/// The output is the normalized connector name, which is the one the modesetting driver uses, e.g.
+/// HDMI-1 for HDMI-A-1. DP MST outputs are named after their path by modesetting, so they don't
+/// match.
+fn format_xrandr_cmd(display: &Display) -> String {
+    let (x, y) = display.mode.size();
+    format!(
+        "xrandr --output {} --mode {}x{} --rate {}",
+        names::normalize(&display.connector),
+        x,
+        y,
+        format_refresh(refresh_rate(&display.mode))
+    )
+}
+
+/// Format the mode list of a display, one `WxH@R` per line.
+fn format_modes(display: &Display, output: &Output) -> String {
+    let mut block = format!("{}:", display.connector);
+    for mode in display.modes.iter().filter(|m| output.modes.matches(m)) {
+        block.push_str("\n  ");
+        block.push_str(&format_mode(mode, output));
+    }
+    block
+}
+
+/// Format a mode as `WIDTHxHEIGHT@REFRESH`, with the frame time if requested.
+fn format_mode(mode: &Mode, output: &Output) -> String {
+    let (x, y) = mode.size();
+    let refresh = refresh_rate(mode);
+    if output.frametime {
+        format!("{}x{}@{}", x, y, format_frametime(refresh))
+    } else {
+        format!("{}x{}@{}", x, y, format_refresh(refresh))
+    }
+}
+
+/// Format a stable display ID, hashed from the EDID manufacturer, product and serial
+///
+/// The ID is the first 8 hex characters of the SHA-256 of the identity. It survives connector
+/// changes, unlike the connector name, which is used instead when there's no EDID.
+fn format_id(display: &Display) -> String {
+    let id = match &display.edid {
+        Some(edid) => edid.identity(),
+        None => return display.connector.clone(),
+    };
+    let hash = Sha256::digest(format!(
+        "{}:{}:{}:{}",
+        id.manufacturer,
+        id.product,
+        id.serial,
+        id.serial_string.unwrap_or_default()
+    ));
+    hash[..4].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Format a block reconciling the panel's maximum, the mode list's maximum and the current mode.
+///
+/// The kernel doesn't expose link training limits, so a mode list below the panel's maximum
+/// can only be blamed on the cable or the GPU as a whole.
+fn format_capabilities(display: &Display) -> String {
+    let res = |res: Option<(u32, u32)>| match res {
+        Some((x, y)) => format!("{}x{}", x, y),
+        None => "unknown".to_string(),
+    };
+    let panel = display.edid.as_ref().and_then(|e| e.max_resolution());
+    let modes = max_mode_size(&display.modes);
+    let (x, y) = display.mode.size();
+    let verdict = match (panel, modes) {
+        (Some((px, py)), Some((mx, my))) if mx * my < px * py => {
+            "mode list is below the panel maximum (limited by the cable or GPU)"
+        }
+        (Some(_), Some(_)) => "panel maximum available",
+        _ => "unknown",
+    };
+    format!(
+        "{}:\n  panel (EDID): {}\n  modes (DRM): {}\n  current: {}x{}\n  limit: {}",
+        display.connector,
+        res(panel),
+        res(modes),
+        x,
+        y,
+        verdict
+    )
+}
+
+/// Largest mode size of a mode list, by area
+fn max_mode_size(modes: &[Mode]) -> Option<(u32, u32)> {
+    modes
+        .iter()
+        .map(|m| (u32::from(m.size().0), u32::from(m.size().1)))
+        .max_by_key(|(x, y)| x * y)
+}
+
+/// Format a display as `key=value` pairs.
+fn format_kv(display: &Display) -> String {
+    let (x, y) = display.mode.size();
+    format!(
+        "width={} height={} refresh={} connector={}",
+        x,
+        y,
+        format_refresh(refresh_rate(&display.mode)),
+        display.connector
+    )
+}
+
+/// Compute the precise refresh rate of a mode, in Hz
+///
+/// `Mode::vrefresh` is rounded to an integer, so compute it from the pixel clock instead.
+pub fn refresh_rate(mode: &Mode) -> f64 {
+    let pixels = u64::from(mode.htotal) * u64::from(mode.vtotal);
+    if pixels == 0 {
+        return f64::from(mode.vrefresh);
+    }
+    // The clock is in kHz
+    f64::from(mode.clock) * 1000.0 / pixels as f64
+}
+
+/// Format a refresh rate with up to two decimals, e.g. 60 or 59.94
+fn format_refresh(refresh: f64) -> String {
+    let refresh = format!("{:.2}", refresh);
+    refresh.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Format the chromaticity coordinates from a display's EDID.
+fn format_chromaticity(display: &Display) -> String {
+    match &display.edid {
+        Some(edid) => {
+            let c = edid.chromaticity();
+            let xy = |(x, y): (f64, f64)| format!("({:.3},{:.3})", x, y);
+            format!("R{} G{} B{} W{}", xy(c.red), xy(c.green), xy(c.blue), xy(c.white))
         }
+        None => "unknown".to_string(),
+    }
+}
+
+/// Format the largest resolution advertised by a display's EDID.
+///
+/// The largest mode from the DRM mode list gets appended when it differs, as the kernel may
+/// filter out some of the modes the EDID advertises.
+fn format_edid_max(display: &Display) -> String {
+    let (x, y) = match display.edid.as_ref().and_then(|e| e.max_resolution()) {
+        Some(res) => res,
+        None => return "unknown".to_string(),
+    };
+    match max_mode_size(&display.modes) {
+        Some((mx, my)) if (mx, my) != (x, y) => {
+            log::info!("EDID max {}x{} differs from mode list max {}x{}", x, y, mx, my);
+            format!("{}x{} (modes max {}x{})", x, y, mx, my)
+        }
+        _ => format!("{}x{}", x, y),
+    }
+}
+
+/// Format the scale factor from the scanout framebuffer to the mode, e.g. `1.25x (2048x1152)`
+///
+/// The factor is the horizontal one, and comes with the framebuffer (logical) size when the
+/// display is scaled. Unscaled displays give `1.0x`.
+///
+/// Under X11 every crtc scans out of one framebuffer spanning the desktop, so a crtc away from 0,0
+/// or a framebuffer larger than the mode means spanning, not scaling, and gives `unknown`.
+fn format_fractional(display: &Display) -> String {
+    let (x, y) = display.mode.size();
+    let (fx, fy) = match display.scanout {
+        Some((fx, fy)) if fx > 0 && fy > 0 => (fx, fy),
+        _ => return "unknown".to_string(),
+    };
+    let spanning = fx > x.into() || fy > y.into();
+    if spanning || display.position.is_some_and(|p| p != (0, 0)) {
+        return "unknown".to_string();
+    }
+    let scale = format!("{:.2}", f64::from(x) / f64::from(fx));
+    // Keep one decimal, e.g. 2.0
+    let mut scale = scale.trim_end_matches('0').to_string();
+    if scale.ends_with('.') {
+        scale.push('0');
+    }
+    if scale == "1.0" {
+        "1.0x".to_string()
+    } else {
+        format!("{}x ({}x{})", scale, fx, fy)
+    }
+}
+
+/// Format a display's resolution as `WIDTHxHEIGHT`.
+fn format_resolution(display: &Display, output: &Output) -> String {
+    let (x, y) = if output.scanout {
+        match display.scanout {
+            Some((x, y)) => (x, y),
+            None => return "unknown".to_string(),
+        }
+    } else {
+        let (x, y) = display.mode.size();
+        (x.into(), y.into())
+    };
+    let mut res = format!("{}x{}", x, y);
+    if output.frametime {
+        res = format!("{}@{}", res, format_frametime(refresh_rate(&display.mode)));
+    }
+    let (nx, ny) = display.native.size();
+    if output.with_native && (x, y) != (nx.into(), ny.into()) {
+        res = format!("{} (native {}x{})", res, nx, ny);
+    }
+    res
+}
+
+/// Format a refresh rate followed by its frame time, e.g. `60 (16.67ms)`
+fn format_frametime(refresh: f64) -> String {
+    format!("{} ({:.2}ms)", format_refresh(refresh), 1000.0 / refresh)
+}
+
+/// Get the `left, top, right, bottom` rectangle of a display on the desktop
+///
+/// Displays without a known position are assumed to be at 0,0.
+fn display_rect(display: &Display) -> (u32, u32, u32, u32) {
+    let (left, top) = display.position.unwrap_or((0, 0));
+    let (x, y) = display.mode.size();
+    (left, top, left + u32::from(x), top + u32::from(y))
+}
+
+/// Get the rectangle around every display
+fn desktop_rect(displays: &[Display]) -> (u32, u32, u32, u32) {
+    let rects: Vec<_> = displays.iter().map(display_rect).collect();
+    (
+        rects.iter().map(|r| r.0).min().unwrap_or(0),
+        rects.iter().map(|r| r.1).min().unwrap_or(0),
+        rects.iter().map(|r| r.2).max().unwrap_or(0),
+        rects.iter().map(|r| r.3).max().unwrap_or(0),
+    )
+}
+
+/// Pick a resolution for a Wine virtual desktop
+///
+/// This is the largest size that fits on every connected display: the smallest current width
+/// and the smallest current height among them. With a single display, it's just its current
+/// resolution.
+fn wine_best_resolution(displays: &[Display]) -> (u16, u16) {
+    let x = displays.iter().map(|d| d.mode.size().0).min().unwrap_or(0);
+    let y = displays.iter().map(|d| d.mode.size().1).min().unwrap_or(0);
+    (x, y)
+}
+
+/// Read a snapshot written by `--json`
+fn read_snapshot<P: AsRef<path::Path>>(file: P) -> eyre::Result<format::Document> {
+    let file = file.as_ref();
+    let text = fs::read_to_string(file)
+        .wrap_err_with(|| format!("failed to read {}", file.display()))?;
+    serde_json::from_str(&text).wrap_err_with(|| format!("invalid snapshot {}", file.display()))
+}
+
+/// Read and validate an EDID binary file
+fn read_edid_file<P: AsRef<path::Path>>(file: P) -> eyre::Result<edid::Edid> {
+    let file = file.as_ref();
+    let bytes = fs::read(file).wrap_err_with(|| format!("failed to read {}", file.display()))?;
+    if bytes.len() % 128 != 0 {
+        return Err(eyre::eyre!("{} is not a whole number of EDID blocks", file.display()));
     }
-    // nVidia GPUs don't expose the encoder (and thus neither the crtc)
-    log::warn!(
-        "Could not detect current mode for display {:?},",
-        connector.interface()
-    );
-    log::warn!("reading native resolution");
-    return Ok(connector.modes()[0]);
+    let edid = edid::Edid::parse(bytes)
+        .wrap_err_with(|| format!("invalid EDID file {}", file.display()))?;
+    if !edid.checksum_valid() {
+        return Err(eyre::eyre!("invalid EDID checksum in {}", file.display()));
+    }
+    Ok(edid)
 }
 
 /// Increase `log::LevelFilter` by one level
@@ -260,3 +2019,192 @@ fn decrement_loglevel(level: log::LevelFilter) -> log::LevelFilter {
         Trace => Debug,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_of_single_display() {
+        assert!(check_selection(0, 1).is_ok());
+        assert!(check_selection(1, 1).is_err());
+    }
+
+    #[test]
+    fn selection_out_of_range() {
+        assert!(check_selection(2, 2).is_err());
+        assert!(check_selection(usize::MAX, 2).is_err());
+        assert!(check_selection(0, 0).is_err());
+    }
+
+    /// A 60Hz display on a simulated card
+    fn fixture(card: &str, connector: &str, (x, y): (u16, u16)) -> Display {
+        let mode = Mode {
+            clock: u32::from(x) * u32::from(y) * 60 / 1000,
+            hdisplay: x,
+            hsync_start: x,
+            hsync_end: x,
+            htotal: x,
+            vdisplay: y,
+            vsync_start: y,
+            vsync_end: y,
+            vtotal: y,
+            vrefresh: 60,
+            flags: 0,
+            preferred: true,
+        };
+        Display {
+            card: card.to_string(),
+            connector: connector.to_string(),
+            internal: connector.starts_with("eDP"),
+            mode,
+            native: mode,
+            position: Some((0, 0)),
+            scanout: Some((x.into(), y.into())),
+            modes: vec![mode],
+            edid: None,
+            edid_source: edid::Source::Unknown,
+            tearing: None,
+            hdr: None,
+            vblank: None,
+        }
+    }
+
+    /// An integrated GPU driving the laptop panel, and a discrete one with the HDMI port
+    fn hybrid_cards() -> Vec<Vec<Display>> {
+        vec![
+            vec![fixture("card0", "eDP-1", (1920, 1080)), fixture("card0", "DP-1", (2560, 1440))],
+            vec![fixture("card1", "HDMI-A-1", (3840, 2160))],
+        ]
+    }
+
+    /// (card, connector) of each display
+    fn attribution(displays: &[Display]) -> Vec<(&str, &str)> {
+        displays.iter().map(|d| (d.card.as_str(), d.connector.as_str())).collect()
+    }
+
+    #[test]
+    fn aggregation_keeps_card_order() {
+        let displays = aggregate_displays(hybrid_cards(), &ScanOptions::default());
+        assert_eq!(
+            attribution(&displays),
+            [("card0", "eDP-1"), ("card0", "DP-1"), ("card1", "HDMI-A-1")]
+        );
+    }
+
+    #[test]
+    fn hybrid_duplicate_listed_per_card() {
+        // The discrete GPU also exposes the panel, through a mux
+        let mut cards = hybrid_cards();
+        cards[1].push(fixture("card1", "eDP-1", (1920, 1080)));
+        let displays = aggregate_displays(cards, &ScanOptions::default());
+        assert_eq!(displays.len(), 4);
+        assert_eq!(attribution(&displays)[0], ("card0", "eDP-1"));
+        assert_eq!(attribution(&displays)[3], ("card1", "eDP-1"));
+    }
+
+    #[test]
+    fn filter_and_sort_span_cards() {
+        let scan = ScanOptions {
+            filter: Some("width>=2560".parse().unwrap()),
+            sort: Some(SortKey::Name),
+            ..Default::default()
+        };
+        let displays = aggregate_displays(hybrid_cards(), &scan);
+        assert_eq!(attribution(&displays), [("card0", "DP-1"), ("card1", "HDMI-A-1")]);
+    }
+
+    /// Connectors of the hybrid fixture displays matching a filter
+    fn filtered(expr: &str) -> Vec<String> {
+        let filter: filter::Filter = expr.parse().unwrap();
+        let displays = aggregate_displays(hybrid_cards(), &ScanOptions::default());
+        displays.into_iter().filter(|d| filter.matches(d)).map(|d| d.connector).collect()
+    }
+
+    /// Error message of an invalid filter
+    fn filter_error(expr: &str) -> String {
+        match expr.parse::<filter::Filter>() {
+            Ok(_) => panic!("{} parsed", expr),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn filter_and_binds_tighter_than_or() {
+        let both = ["eDP-1", "HDMI-A-1"];
+        assert_eq!(filtered("width>=2560 && type==HDMI || connector==eDP-1"), both);
+        assert_eq!(filtered("connector==eDP-1 || width>=2560 && type==HDMI"), both);
+        assert_eq!(filtered("width<3000 && refresh>=60 && type!=eDP"), ["DP-1"]);
+    }
+
+    #[test]
+    fn filter_number_and_text_fields() {
+        assert_eq!(filtered("height==1440"), ["DP-1"]);
+        assert_eq!(filtered("type==HDMI"), ["HDMI-A-1"]);
+        assert_eq!(filtered("connector==HDMI-A-1"), ["HDMI-A-1"]);
+        assert!(filtered("connector==HDMI").is_empty());
+        assert_eq!(filter_error("type>=HDMI"), "'type' can only be compared with == and !=");
+        assert_eq!(filter_error("width==wide"), "invalid number 'wide' in filter");
+    }
+
+    #[test]
+    fn filter_errors_name_the_token() {
+        assert_eq!(filter_error(""), "empty filter");
+        assert_eq!(filter_error("  "), "empty filter");
+        assert_eq!(filter_error("width>=1920 &&"), "filter ends with '&&'");
+        assert_eq!(filter_error("size>1"), "unknown field 'size' in filter");
+        assert_eq!(filter_error("width 1920"), "unexpected '1920' in filter after width");
+        let error = filter_error("width>1 type==DP");
+        assert_eq!(error, "unexpected 'type' in filter, expected && or ||");
+    }
+
+    #[test]
+    fn simple_prefers_lit_primary_external() {
+        let mut displays = aggregate_displays(hybrid_cards(), &ScanOptions::default());
+        // Every fixture is lit at 0,0, so the largest external display wins
+        assert_eq!(displays[simple_display(&displays)].connector, "HDMI-A-1");
+        displays[2].position = Some((2560, 0));
+        assert_eq!(displays[simple_display(&displays)].connector, "DP-1");
+        displays[1].position = None;
+        displays[2].position = None;
+        assert_eq!(displays[simple_display(&displays)].connector, "eDP-1");
+        // The scan order doesn't matter
+        displays.reverse();
+        assert_eq!(displays[simple_display(&displays)].connector, "eDP-1");
+    }
+
+    #[test]
+    fn fractional_scale_and_spanning() {
+        let mut display = fixture("card0", "DP-1", (2560, 1440));
+        assert_eq!(format_fractional(&display), "1.0x");
+        display.scanout = Some((2048, 1152));
+        assert_eq!(format_fractional(&display), "1.25x (2048x1152)");
+        // Second of two displays side by side on one X11 framebuffer
+        display.scanout = Some((5120, 1440));
+        display.position = Some((2560, 0));
+        assert_eq!(format_fractional(&display), "unknown");
+    }
+
+    #[test]
+    fn natural_order() {
+        use std::cmp::Ordering::*;
+        assert_eq!(natural_cmp("DP-9", "DP-10"), Less);
+        assert_eq!(natural_cmp("DP-10", "DP-9"), Greater);
+        assert_eq!(natural_cmp("DP-2", "DP-2"), Equal);
+        // Same value, told apart but still next to each other
+        assert_eq!(natural_cmp("DP-01", "DP-1"), Less);
+        assert_eq!(natural_cmp("DP-01", "DP-2"), Less);
+        assert_eq!(natural_cmp("DP-010", "DP-9"), Greater);
+        // Text and numbers mixed
+        assert_eq!(natural_cmp("HDMI-A-2", "HDMI-A-10"), Less);
+        assert_eq!(natural_cmp("card10-DP-1", "card2-DP-3"), Greater);
+        assert_eq!(natural_cmp("DP-1-8", "DP-1-10"), Less);
+        assert_eq!(natural_cmp("DP-1", "HDMI-A-1"), Less);
+        assert_eq!(natural_cmp("DP-1", "DP-A"), Less);
+        // An empty string or a missing number comes first
+        assert_eq!(natural_cmp("", "1"), Less);
+        assert_eq!(natural_cmp("1", ""), Greater);
+        assert_eq!(natural_cmp("", ""), Equal);
+        assert_eq!(natural_cmp("DP-", "DP-1"), Less);
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Display modes
+
+/// Positive horizontal sync, same value as DRM's
+pub const FLAG_PHSYNC: u32 = 1 << 0;
+/// Negative horizontal sync, same value as DRM's
+pub const FLAG_NHSYNC: u32 = 1 << 1;
+/// Positive vertical sync, same value as DRM's
+pub const FLAG_PVSYNC: u32 = 1 << 2;
+/// Negative vertical sync, same value as DRM's
+pub const FLAG_NVSYNC: u32 = 1 << 3;
+/// Interlaced mode, same value as DRM's
+pub const FLAG_INTERLACE: u32 = 1 << 4;
+
+/// Timings of a display mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mode {
+    /// Pixel clock, in kHz
+    pub clock: u32,
+    pub hdisplay: u16,
+    pub hsync_start: u16,
+    pub hsync_end: u16,
+    pub htotal: u16,
+    pub vdisplay: u16,
+    pub vsync_start: u16,
+    pub vsync_end: u16,
+    pub vtotal: u16,
+    /// Refresh rate as reported by the driver, rounded to an integer
+    pub vrefresh: u32,
+    /// Sync polarity and interlacing flags (`FLAG_*`)
+    pub flags: u32,
+    /// Whether the driver flags this mode as preferred
+    pub preferred: bool,
+}
+
+impl Mode {
+    /// Visible size, in pixels
+    pub fn size(&self) -> (u16, u16) {
+        (self.hdisplay, self.vdisplay)
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Canonical connector names, for `--normalize-names`
+//!
+//! A name is split into a type and a trailing index, the type is renamed with this table and the
+//! two are joined with a dash:
+//!
+//! | Names                                   | Normalized |
+//! |-----------------------------------------|------------|
+//! | `HDMI-A-1` (kernel), `HDMI-1`, `HDMI1`  | `HDMI-1`   |
+//! | `DisplayPort-1` (amdgpu X11), `DP1`     | `DP-1`     |
+//! | `eDP1`, `VGA1`... (intel X11)           | `eDP-1`... |
+//!
+//! Other kernel names are already canonical. The index is kept as is, so the 0-based names of
+//! some X11 drivers still differ from the kernel's 1-based ones.
+
+/// Connector types with another name, and their canonical names
+const ALIASES: [(&str, &str); 2] = [("HDMI-A", "HDMI"), ("DisplayPort", "DP")];
+
+/// Normalize a connector name, e.g. `HDMI-A-1` to `HDMI-1`
+///
+/// Normalized names are left unchanged, and so are names without a trailing index.
+pub fn normalize(name: &str) -> String {
+    let kind = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let index = &name[kind.len()..];
+    if index.is_empty() {
+        return name.to_string();
+    }
+    let kind = kind.strip_suffix('-').unwrap_or(kind);
+    let kind = match ALIASES.iter().find(|(alias, _)| *alias == kind) {
+        Some((_, canonical)) => canonical,
+        None => kind,
+    };
+    format!("{}-{}", kind, index)
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Lookups in sysfs and the udev database
+
+use std::fs;
+use std::path;
+
+/// Seat of devices without an explicit assignment
+const DEFAULT_SEAT: &str = "seat0";
+
+/// Major and minor numbers of a device node
+#[cfg(unix)]
+fn device_number<P: AsRef<path::Path>>(node: P) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let dev = fs::metadata(node).ok()?.rdev();
+    // Same encoding as glibc's major() and minor()
+    let major = (dev >> 8) & 0xfff | (dev >> 32) & !0xfff;
+    let minor = dev & 0xff | (dev >> 12) & !0xff;
+    Some((major, minor))
+}
+
+/// Device nodes have no numbers outside of unix
+#[cfg(not(unix))]
+fn device_number<P: AsRef<path::Path>>(_node: P) -> Option<(u64, u64)> {
+    None
+}
+
+/// Read a property from the udev database entry of a character device
+fn udev_property<P: AsRef<path::Path>>(node: P, key: &str) -> Option<String> {
+    let (major, minor) = device_number(node)?;
+    let data = fs::read_to_string(format!("/run/udev/data/c{}:{}", major, minor)).ok()?;
+    let prefix = format!("E:{}=", key);
+    data.lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .map(str::to_string)
+}
+
+/// Get the logind seat a device node is assigned to
+///
+/// Devices not assigned to a seat by udev belong to seat0.
+pub fn seat<P: AsRef<path::Path>>(node: P) -> String {
+    udev_property(node, "ID_SEAT").unwrap_or_else(|| DEFAULT_SEAT.to_string())
+}
+
+/// Get the framebuffer device of a DRM card, e.g. fb0
+///
+/// The fbdev emulation of a card shares its parent device. Returns `None` when the card has
+/// no framebuffer device.
+pub fn fbdev(card: &str) -> Option<String> {
+    let device = fs::canonicalize(format!("/sys/class/drm/{}/device", card)).ok()?;
+    fs::read_dir("/sys/class/graphics")
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("fb"))
+        .find(|e| fs::canonicalize(e.path().join("device")).is_ok_and(|d| d == device))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+}
+
+/// Find where the kernel got the EDID of a connector
+///
+/// An EDID the kernel exposes without an override was read from the display. The debugfs
+/// override can only be checked as root, so it's unknown otherwise.
+#[cfg(target_os = "linux")]
+pub fn edid_source(card: &str, connector: &str) -> crate::edid::Source {
+    use crate::edid::Source;
+    match kernel_edid_override(card, connector) {
+        Some(true) => return Source::Overridden,
+        Some(false) => (),
+        None => return Source::Unknown,
+    }
+    match fs::read(format!("/sys/class/drm/{}-{}/edid", card, connector)) {
+        Ok(edid) if edid.is_empty() => Source::Unknown,
+        Ok(_) => Source::Live,
+        Err(_) => Source::Unknown,
+    }
+}
+
+/// Check whether the kernel replaces the EDID of a connector
+///
+/// `edid_firmware` entries are either `CONNECTOR:FILE` or a lone `FILE` for every connector.
+/// Returns `None` when there's no firmware override and the debugfs one can't be read.
+#[cfg(target_os = "linux")]
+fn kernel_edid_override(card: &str, connector: &str) -> Option<bool> {
+    let firmware = ["drm", "drm_kms_helper"].iter().any(|module| {
+        let param = format!("/sys/module/{}/parameters/edid_firmware", module);
+        let entries = fs::read_to_string(param).unwrap_or_default();
+        entries
+            .trim()
+            .split(',')
+            .filter(|e| !e.is_empty() && *e != "(null)")
+            .any(|e| e.split_once(':').is_none_or(|(name, _)| name == connector))
+    });
+    // The debugfs directory of a card is named after its minor number
+    let minor = card.trim_start_matches("card");
+    let debugfs = format!("/sys/kernel/debug/dri/{}/{}/edid_override", minor, connector);
+    if firmware {
+        return Some(true);
+    }
+    fs::read(debugfs).ok().map(|edid| !edid.is_empty())
+}
+
+/// Get the output names of the touchscreens, from their `WL_OUTPUT` udev property
+///
+/// Touchscreens without that property give `None`. Returns `None` when the udev database
+/// can't be read.
+pub fn touchscreens() -> Option<Vec<Option<String>>> {
+    if !path::Path::new("/run/udev/data").is_dir() {
+        return None;
+    }
+    let entries = fs::read_dir("/dev/input").ok()?;
+    let touchscreens = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("event"))
+        .map(|e| e.path())
+        .filter(|node| udev_property(node, "ID_INPUT_TOUCHSCREEN").as_deref() == Some("1"))
+        .map(|node| udev_property(node, "WL_OUTPUT"))
+        .collect();
+    Some(touchscreens)
+}
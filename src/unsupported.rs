@@ -0,0 +1,53 @@
+// Copyright (c) 2021 rokbma & the johncena141 hacker group on 1337x.to
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Fallback for platforms without DRM
+//!
+//! Reading displays fails at runtime, so the rest of rres still builds on other platforms.
+
+use std::path;
+
+use crate::{CardInfo, Display, ScanOptions};
+
+fn unsupported() -> eyre::Report {
+    eyre::eyre!("unsupported platform, reading displays requires Linux DRM")
+}
+
+/// Always fails
+pub fn find_cards(_card: Option<&str>) -> eyre::Result<Vec<path::PathBuf>> {
+    Err(unsupported())
+}
+
+/// Always fails
+pub fn probe(_file: &path::Path) -> eyre::Result<()> {
+    Err(unsupported())
+}
+
+/// Always fails
+pub fn card_info(_file: &path::Path) -> eyre::Result<CardInfo> {
+    Err(unsupported())
+}
+
+/// Always fails
+pub fn driver_caps(_file: &path::Path) -> eyre::Result<Vec<(&'static str, Option<u64>)>> {
+    Err(unsupported())
+}
+
+/// Always fails
+pub fn read_card(_file: &path::Path, _scan: &ScanOptions) -> eyre::Result<Vec<Display>> {
+    Err(unsupported())
+}